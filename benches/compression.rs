@@ -0,0 +1,167 @@
+// Measures how much smaller compressed posting lists are than raw ones on the corpus in `data/`,
+// both in the index and on the wire, and how long it takes to encode and decode them. Run with
+// `cargo bench --bench compression`.
+//
+// The memory taken by the postings is measured by counting the bytes held by the allocator
+// while each layout is built, so it includes every node, key and spare capacity.
+
+use ngram::database::{Database, Posting};
+use ngram::message::Response;
+use ngram::multimap::ConcurrentMultiMap;
+use ngram::varint::{read_sorted, write_sorted, write_varint};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The system allocator, keeping count of the bytes currently allocated
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        ALLOCATED.fetch_add(new_size, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// The bytes `build` leaves allocated, measured while the value it returns is still alive
+fn measure<T>(build: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let value = build();
+    let after = ALLOCATED.load(Ordering::SeqCst);
+    drop(value);
+    after - before
+}
+
+/// The number of buckets of the multimaps built, as in the database
+const BUCKETS: usize = 128;
+
+fn main() {
+    let database = Database::new();
+    let mut paths: Vec<_> = fs::read_dir("data")
+        .expect("run from the root of the repository")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().is_some_and(|name| name != "words.txt"))
+        .collect();
+    paths.sort();
+    for path in &paths {
+        database.publish(fs::read_to_string(path).unwrap()).unwrap();
+    }
+    let (terms, _) = database.list_terms("", 0, usize::MAX);
+    println!("{} documents, {} distinct words", paths.len(), terms.len());
+
+    // Every posting list of the index, as it is kept in memory
+    let lists: Vec<(Vec<usize>, Vec<Vec<usize>>)> = terms
+        .iter()
+        .map(|term| database.phrase(&term.term).into_iter().unzip())
+        .collect();
+
+    // Every posting as its own node of the multimap, with a copy of the key and its positions
+    // in a `Vec<usize>`, against one node per key holding a single buffer appended to in order
+    // of id, as a `PostingList` is, with the gaps between ids and positions written as
+    // variable-length integers
+    let raw = measure(|| {
+        let map = ConcurrentMultiMap::new(BUCKETS);
+        for (term, (ids, positions)) in terms.iter().zip(&lists) {
+            for (&id, positions) in ids.iter().zip(positions) {
+                let positions = positions.clone();
+                map.set(term.term.clone(), Posting { id, positions });
+            }
+        }
+        map
+    });
+    let packed = measure(|| {
+        let map = ConcurrentMultiMap::new(BUCKETS);
+        for (term, (ids, positions)) in terms.iter().zip(&lists) {
+            for (&id, positions) in ids.iter().zip(positions) {
+                map.update(term.term.clone(), |list: &mut Option<(Vec<u8>, usize)>| {
+                    let (bytes, last) = list.get_or_insert_with(Default::default);
+                    write_varint(bytes, (id - *last) as u64);
+                    write_sorted(bytes, positions);
+                    *last = id;
+                });
+            }
+        }
+        map
+    });
+    report("postings in memory", raw, packed);
+
+    // The postings of each key in a segment, written with fixed-size integers against gaps
+    // between ids and positions written as variable-length integers
+    let mut raw = 0;
+    let mut packed = 0;
+    for (ids, positions) in &lists {
+        raw += 4;
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, ids.len() as u64);
+        let mut previous = 0;
+        for (&id, positions) in ids.iter().zip(positions) {
+            raw += 8 + 4 + 8 * positions.len();
+            write_varint(&mut bytes, (id - previous) as u64);
+            write_sorted(&mut bytes, positions);
+            previous = id;
+        }
+        packed += bytes.len();
+    }
+    report("segment postings", raw, packed);
+
+    let mut raw = 0;
+    let mut compressed = 0;
+    for (ids, _) in &lists {
+        raw += Response::SearchSuccess(ids.clone()).to_bytes().len();
+        compressed += Response::CompressedSearchSuccess(ids.clone())
+            .to_bytes()
+            .len();
+    }
+    report("search responses", raw, compressed);
+
+    let positions: Vec<&Vec<usize>> = lists.iter().flat_map(|(_, p)| p).collect();
+    let count: usize = positions.iter().map(|p| p.len()).sum();
+    let start = Instant::now();
+    let mut encoded = Vec::new();
+    for positions in &positions {
+        let mut bytes = Vec::new();
+        write_sorted(&mut bytes, positions);
+        encoded.push(bytes);
+    }
+    let encoding = start.elapsed();
+    let start = Instant::now();
+    let mut decoded = 0;
+    for bytes in &encoded {
+        decoded += read_sorted(&mut &bytes[..]).unwrap().len();
+    }
+    let decoding = start.elapsed();
+    assert_eq!(decoded, count);
+    println!(
+        "{:<20} {:>10.1} ns per position to encode, {:.1} to decode",
+        "speed",
+        encoding.as_nanos() as f64 / count as f64,
+        decoding.as_nanos() as f64 / count as f64
+    );
+}
+
+fn report(what: &str, raw: usize, compressed: usize) {
+    println!(
+        "{:<20} {:>10} bytes raw, {:>10} compressed ({:.1}x smaller)",
+        what,
+        raw,
+        compressed,
+        raw as f64 / compressed as f64
+    );
+}
@@ -0,0 +1,329 @@
+use crate::lz;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+// Documents are packed one after another into blocks of about `BLOCK_SIZE` bytes, and each block
+// is compressed as a whole once it is full, since a block of text compresses far better than a
+// single short document does. The block being filled is kept as it is. Reading a document
+// decompresses its whole block, so the last few blocks read are kept in a cache, which makes
+// reading documents in order of id cheap.
+//
+// The text of a deleted or replaced document stays in its block until every document in the
+// block is gone, at which point the block is dropped.
+//
+// A `ShardedBlobStore` spreads documents across several of these by id, each behind its own
+// lock, and hands out ids from an atomic counter. Documents are only ever added at the end of a
+// shard. A full block is copied out under a read lock and compressed with no lock held, and a
+// removed document is read under a read lock too, so a shard is only locked for writing while a
+// document is copied into it, a compressed block is put in place or a location is updated.
+// Reading a document never waits on a block being compressed or decompressed for a change.
+
+/// The size a block is filled to before it is compressed
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// The number of decompressed blocks kept in the cache
+const CACHED_BLOCKS: usize = 8;
+
+/// The number of shards a `ShardedBlobStore` spreads documents across
+pub const SHARDS: usize = 16;
+
+/// Where the text of a document is kept
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    block: usize,
+    offset: usize,
+    len: usize,
+}
+
+struct Block {
+    /// The compressed contents of the block
+    data: Box<[u8]>,
+    /// The number of bytes in the block that belong to documents still in the store
+    live: usize,
+}
+
+/// A store of document texts, compressed in blocks
+pub struct BlobStore {
+    /// The location of every document, with `None` in place of deleted ones
+    locations: Vec<Option<Location>>,
+    /// Every full block, compressed
+    blocks: Vec<Block>,
+    /// The block being filled, which comes after the full ones
+    open: Vec<u8>,
+    /// The number of bytes in the open block that belong to documents still in the store
+    open_live: usize,
+    /// The most recently read blocks, decompressed, with the most recent first
+    cache: Mutex<VecDeque<(usize, Vec<u8>)>>,
+}
+
+impl Default for BlobStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlobStore {
+    /// Create a new empty store
+    pub fn new() -> Self {
+        Self {
+            locations: Vec::new(),
+            blocks: Vec::new(),
+            open: Vec::new(),
+            open_live: 0,
+            cache: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The number of documents in the store, deleted ones included
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Whether the store has no documents
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Add a document to the store, or an empty slot in place of a deleted one if `doc` is
+    /// `None`, and return its id
+    pub fn push(&mut self, doc: Option<String>) -> usize {
+        let location = doc.map(|doc| self.append(&doc));
+        self.locations.push(location);
+        self.locations.len() - 1
+    }
+
+    /// Whether the document with the given id is in the store
+    pub fn contains(&self, id: usize) -> bool {
+        matches!(self.locations.get(id), Some(Some(_)))
+    }
+
+    /// Get the text of the document with the given id, or `None` if the id is invalid or the
+    /// document was deleted
+    pub fn get(&self, id: usize) -> Option<String> {
+        let location = (*self.locations.get(id)?)?;
+        // An empty document takes up no space, so its block may already have been dropped
+        if location.len == 0 {
+            return Some(String::new());
+        }
+        let range = location.offset..location.offset + location.len;
+        if location.block == self.blocks.len() {
+            return String::from_utf8(self.open[range].to_vec()).ok();
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        match cache.iter().position(|(block, _)| *block == location.block) {
+            Some(i) => {
+                let entry = cache.remove(i).unwrap();
+                cache.push_front(entry);
+            }
+            None => {
+                let data = lz::decompress(&self.blocks[location.block].data)?;
+                cache.push_front((location.block, data));
+                cache.truncate(CACHED_BLOCKS);
+            }
+        }
+        String::from_utf8(cache[0].1.get(range)?.to_vec()).ok()
+    }
+
+    /// Remove the document with the given id from the store and return its text, or `None` if
+    /// the id is invalid or the document was already deleted
+    pub fn take(&mut self, id: usize) -> Option<String> {
+        let doc = self.get(id)?;
+        self.remove(id).then_some(doc)
+    }
+
+    /// Remove the document with the given id from the store, and return whether it was there
+    fn remove(&mut self, id: usize) -> bool {
+        let Some(location) = self.locations.get_mut(id).and_then(Option::take) else {
+            return false;
+        };
+        self.release(location);
+        true
+    }
+
+    /// Replace the text of the document with the given id, which must be in the store
+    pub fn set(&mut self, id: usize, doc: String) {
+        if let Some(location) = self.locations[id].take() {
+            self.release(location);
+        }
+        self.locations[id] = Some(self.append(&doc));
+    }
+
+    /// The number of bytes taken up by the blocks
+    pub fn stored_bytes(&self) -> usize {
+        self.open.len() + self.blocks.iter().map(|b| b.data.len()).sum::<usize>()
+    }
+
+    /// The number of the open block and a copy of its contents, if a document of `len` bytes
+    /// doesn't fit in it, so that they can be compressed for `seal` without holding a lock
+    fn full_block(&self, len: usize) -> Option<(usize, Vec<u8>)> {
+        if self.open.is_empty() || self.open.len() + len <= BLOCK_SIZE {
+            return None;
+        }
+        Some((self.blocks.len(), self.open.clone()))
+    }
+
+    /// Close the open block, which is number `block` and `len` bytes long, with `data` as its
+    /// compressed contents. Nothing happens if documents were added to it since it was copied.
+    fn seal(&mut self, block: usize, len: usize, data: Box<[u8]>) {
+        if self.blocks.len() != block || self.open.len() != len {
+            return;
+        }
+        let live = std::mem::take(&mut self.open_live);
+        self.open.clear();
+        self.blocks.push(Block { data, live });
+    }
+
+    /// Add `doc` to the open block, compressing the block first if `doc` doesn't fit in it
+    fn append(&mut self, doc: &str) -> Location {
+        if !self.open.is_empty() && self.open.len() + doc.len() > BLOCK_SIZE {
+            let live = std::mem::take(&mut self.open_live);
+            let data = lz::compress(&std::mem::take(&mut self.open)).into_boxed_slice();
+            self.blocks.push(Block { data, live });
+        }
+        self.open_live += doc.len();
+        let location = Location {
+            block: self.blocks.len(),
+            offset: self.open.len(),
+            len: doc.len(),
+        };
+        self.open.extend_from_slice(doc.as_bytes());
+        location
+    }
+
+    /// Note that the text at `location` no longer belongs to any document
+    fn release(&mut self, location: Location) {
+        let Some(block) = self.blocks.get_mut(location.block) else {
+            self.open_live -= location.len;
+            return;
+        };
+        block.live -= location.len;
+        if block.live == 0 {
+            block.data = Box::default();
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|(cached, _)| *cached != location.block);
+        }
+    }
+}
+
+/// A store of document texts split into shards by id, which can be read and added to at once
+pub struct ShardedBlobStore {
+    /// The document with id `id` is number `id / SHARDS` in shard `id % SHARDS`
+    shards: Box<[RwLock<BlobStore>]>,
+    /// The number of ids handed out
+    len: AtomicUsize,
+}
+
+impl Default for ShardedBlobStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShardedBlobStore {
+    /// Create a new empty store
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::new(BlobStore::new())).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of ids handed out, including those of deleted documents and of documents not
+    /// yet stored
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Whether no ids have been handed out
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hand out the next id. The document can't be read until it is stored with `insert`.
+    pub fn reserve(&self) -> usize {
+        self.len.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Store the text of the document with the given id, which must have been handed out by
+    /// `reserve`, or leave its slot empty if `doc` is `None`
+    pub fn insert(&self, id: usize, doc: Option<String>) {
+        let (shard, local) = self.locate(id);
+        if let Some(doc) = &doc {
+            Self::seal_full_block(shard, doc.len());
+        }
+        let mut shard = shard.write().unwrap();
+        // Ids may be stored out of order, so the slots of those skipped are left empty until
+        // they are stored
+        while shard.len() < local {
+            shard.push(None);
+        }
+        match doc {
+            Some(doc) if local < shard.len() => shard.set(local, doc),
+            doc if local == shard.len() => {
+                shard.push(doc);
+            }
+            _ => {}
+        }
+    }
+
+    /// Hand out the next id and store `doc` under it
+    pub fn push(&self, doc: Option<String>) -> usize {
+        let id = self.reserve();
+        self.insert(id, doc);
+        id
+    }
+
+    /// Whether the document with the given id is in the store
+    pub fn contains(&self, id: usize) -> bool {
+        let (shard, local) = self.locate(id);
+        shard.read().unwrap().contains(local)
+    }
+
+    /// Get the text of the document with the given id, or `None` if the id is invalid, the
+    /// document was deleted or it isn't stored yet
+    pub fn get(&self, id: usize) -> Option<String> {
+        let (shard, local) = self.locate(id);
+        shard.read().unwrap().get(local)
+    }
+
+    /// Remove the document with the given id from the store and return its text, or `None` if
+    /// it isn't in the store
+    pub fn take(&self, id: usize) -> Option<String> {
+        let (shard, local) = self.locate(id);
+        let doc = shard.read().unwrap().get(local)?;
+        shard.write().unwrap().remove(local).then_some(doc)
+    }
+
+    /// Replace the text of the document with the given id, which must be in the store
+    pub fn set(&self, id: usize, doc: String) {
+        let (shard, local) = self.locate(id);
+        Self::seal_full_block(shard, doc.len());
+        shard.write().unwrap().set(local, doc);
+    }
+
+    /// The number of bytes taken up by the blocks of every shard
+    pub fn stored_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().stored_bytes())
+            .sum()
+    }
+
+    /// Compress the open block of `shard` if a document of `len` bytes doesn't fit in it, without
+    /// holding a lock on the shard while compressing. If another document is added to the shard
+    /// in the meantime, the block is left open and `append` compresses it instead.
+    fn seal_full_block(shard: &RwLock<BlobStore>, len: usize) {
+        let Some((block, open)) = shard.read().unwrap().full_block(len) else {
+            return;
+        };
+        let data = lz::compress(&open).into_boxed_slice();
+        shard.write().unwrap().seal(block, open.len(), data);
+    }
+
+    /// The shard holding the document with the given id, and its number within the shard
+    fn locate(&self, id: usize) -> (&RwLock<BlobStore>, usize) {
+        (&self.shards[id % SHARDS], id / SHARDS)
+    }
+}
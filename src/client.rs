@@ -1,4 +1,6 @@
+use crate::database::{Association, Scoring};
 use crate::message::*;
+use crate::vocabulary::PatternKind;
 use std::default::Default;
 use std::io::Write;
 use std::net::{IpAddr, SocketAddr};
@@ -47,14 +49,35 @@ impl Client {
     // You can read the contents of a file with `let s = std::fs::read_to_string(path)`.
     pub fn publish_from_path(&self, path: &str) -> Option<Response> {
         let contents = std::fs::read_to_string(path).ok()?;
-        let request = Request::Publish{ doc: contents };
+        let request = Request::Publish { doc: contents };
+        self.send(&request)
+    }
+
+    /// Read the file at `path` and send a `PublishWithMetadata` request to the server with its
+    /// contents and the given metadata. Return the response from the server.
+    pub fn publish_from_path_with_metadata(
+        &self,
+        path: &str,
+        title: &str,
+        author: &str,
+        year: u32,
+    ) -> Option<Response> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        let request = Request::PublishWithMetadata {
+            doc: contents,
+            title: title.to_string(),
+            author: author.to_string(),
+            year,
+        };
         self.send(&request)
     }
     // TODO:
     // Send a `Search` request to the server with the given `word`. Return the response from the
     // server.
     pub fn search(&self, word: &str) -> Option<Response> {
-        let request = Request::Search { word: word.to_string() };
+        let request = Request::Search {
+            word: word.to_string(),
+        };
         self.send(&request)
     }
     // TODO:
@@ -64,4 +87,198 @@ impl Client {
         let request = Request::Retrieve { id };
         self.send(&request)
     }
+
+    /// Send a `SearchNgram` request to the server for the word sequence `ngram`. Return the
+    /// response from the server.
+    pub fn search_ngram(&self, ngram: &str) -> Option<Response> {
+        let request = Request::SearchNgram {
+            ngram: ngram.to_string(),
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Count` request to the server for the word sequence `ngram`. Return the response
+    /// from the server.
+    pub fn count(&self, ngram: &str) -> Option<Response> {
+        let request = Request::Count {
+            ngram: ngram.to_string(),
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Timeline` request to the server for the word sequence `ngram` with the given
+    /// normalization and smoothing. Return the response from the server.
+    pub fn timeline(&self, ngram: &str, normalize: bool, smoothing: u32) -> Option<Response> {
+        let request = Request::Timeline {
+            ngram: ngram.to_string(),
+            normalize,
+            smoothing,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Query` request to the server with the boolean query `query`. Return the response
+    /// from the server.
+    pub fn query(&self, query: &str) -> Option<Response> {
+        let request = Request::Query {
+            query: query.to_string(),
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Phrase` request to the server for the words of `phrase`. Return the response from
+    /// the server.
+    pub fn phrase(&self, phrase: &str) -> Option<Response> {
+        let request = Request::Phrase {
+            phrase: phrase.to_string(),
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Near` request to the server for the phrases `left` and `right` within `distance`
+    /// words of each other. Return the response from the server.
+    pub fn near(&self, left: &str, right: &str, distance: usize) -> Option<Response> {
+        let request = Request::Near {
+            left: left.to_string(),
+            right: right.to_string(),
+            distance,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Pattern` request to the server for the words matching `pattern`. Return the
+    /// response from the server.
+    pub fn search_pattern(&self, pattern: &str, kind: PatternKind) -> Option<Response> {
+        let request = Request::Pattern {
+            pattern: pattern.to_string(),
+            kind,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `FuzzySearch` request to the server for the words within `max_distance` edits of
+    /// `word`. Return the response from the server.
+    pub fn fuzzy_search(&self, word: &str, max_distance: usize) -> Option<Response> {
+        let request = Request::FuzzySearch {
+            word: word.to_string(),
+            max_distance,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `RankedSearch` request to the server for the `k` documents that best match the
+    /// words of `query`. Return the response from the server.
+    pub fn ranked_search(&self, query: &str, scoring: Scoring, k: usize) -> Option<Response> {
+        let request = Request::RankedSearch {
+            query: query.to_string(),
+            scoring,
+            k,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Concordance` request to the server for up to `limit` occurrences of `term` with
+    /// `window` words of context on each side. Return the response from the server.
+    pub fn concordance(&self, term: &str, window: usize, limit: usize) -> Option<Response> {
+        let request = Request::Concordance {
+            term: term.to_string(),
+            window,
+            limit,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `SnippetSearch` request to the server for the `k` documents that best match the
+    /// words of `query`, each with an excerpt of up to `words` words. Return the response from
+    /// the server.
+    pub fn snippets(
+        &self,
+        query: &str,
+        scoring: Scoring,
+        k: usize,
+        words: usize,
+    ) -> Option<Response> {
+        let request = Request::SnippetSearch {
+            query: query.to_string(),
+            scoring,
+            k,
+            words,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Collocations` request to the server for the `k` words most strongly associated
+    /// with `term`. Return the response from the server.
+    pub fn collocations(
+        &self,
+        term: &str,
+        window: usize,
+        measure: Association,
+        min_count: usize,
+        k: usize,
+    ) -> Option<Response> {
+        let request = Request::Collocations {
+            term: term.to_string(),
+            window,
+            measure,
+            min_count,
+            k,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Stats` request to the server for the size of the archive and its `top_k` most
+    /// frequent words. Return the response from the server.
+    pub fn stats(&self, top_k: usize) -> Option<Response> {
+        let request = Request::Stats { top_k };
+        self.send(&request)
+    }
+
+    /// Send a `DocStats` request to the server for the size of the document with the index `id`
+    /// and its `top_k` most frequent words. Return the response from the server.
+    pub fn doc_stats(&self, id: usize, top_k: usize) -> Option<Response> {
+        let request = Request::DocStats { id, top_k };
+        self.send(&request)
+    }
+
+    /// Send a `ListTerms` request to the server for a page of the words starting with `prefix`.
+    /// Return the response from the server.
+    pub fn list_terms(&self, prefix: &str, offset: usize, limit: usize) -> Option<Response> {
+        let request = Request::ListTerms {
+            prefix: prefix.to_string(),
+            offset,
+            limit,
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Delete` request to the server for the document with the index `id`. Return the
+    /// response from the server.
+    pub fn delete(&self, id: usize) -> Option<Response> {
+        let request = Request::Delete { id };
+        self.send(&request)
+    }
+
+    /// Read the file at `path` and send a `Replace` request to the server to replace the text of
+    /// the document with the index `id` by its contents. Return the response from the server.
+    pub fn replace_from_path(&self, id: usize, path: &str) -> Option<Response> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        let request = Request::Replace { id, doc: contents };
+        self.send(&request)
+    }
+
+    /// Send a `CompressedSearch` request to the server for the documents containing `word`.
+    /// Return the response from the server.
+    pub fn search_compressed(&self, word: &str) -> Option<Response> {
+        let request = Request::CompressedSearch {
+            word: word.to_string(),
+        };
+        self.send(&request)
+    }
+
+    /// Send a `Snapshot` request to the server to write a snapshot of the archive to its
+    /// storage. Return the response from the server.
+    pub fn snapshot(&self) -> Option<Response> {
+        self.send(&Request::Snapshot)
+    }
 }
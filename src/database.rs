@@ -1,30 +1,912 @@
+use crate::blob::ShardedBlobStore;
+use crate::dedup;
+use crate::message::{
+    read_bool, read_string, read_u32, read_u64, read_usize, write_bool, write_string, write_u32,
+    write_u64, write_usize,
+};
 use crate::multimap::ConcurrentMultiMap;
-use std::sync::Mutex;
+use crate::query::Query;
+use crate::segment::{self, Segment};
+use crate::storage::{read_metadata, write_metadata, Record, Storage};
+use crate::tokenizer::{Token, Tokenizer, WhitespaceTokenizer};
+use crate::varint;
+use crate::vocabulary::{PatternKind, Vocabulary};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 // The archive struct contains two data structures: a ConcurrentMultiMap for storing the
-// reverse index that maps words to the documents they appear in, and a Mutex<Vec<String>> for
-// storing the documents themselves. Since the documents themselves aren't accessed as often, it's
-// ok to keep them behind a single mutex.
+// reverse index that maps words to the documents they appear in, and a ShardedBlobStore for
+// storing the documents themselves. Changes are made one at a time under the `changes` mutex,
+// but the blob store hands out ids from an atomic counter and locks each of its shards
+// separately, so retrieving a document never waits on one being published. A deleted document
+// leaves an empty slot in the store, so that the ids of the other documents don't change.
+//
+// Documents are split into words by the database's `Tokenizer`, and queries are split by the
+// same tokenizer so that they match what was indexed. Besides single words, the reverse index
+// holds every contiguous sequence of up to `max_ngram` words (an "n-gram"), keyed by the words
+// joined with a single space. Stop words, and any n-gram containing one, are left out of the
+// reverse index entirely. Each document appears at most once per key, together with the
+// positions at which the key occurs in it. A position counts words from the start of the
+// document body, stop words included, so that a phrase containing stop words can be narrowed
+// down by the positions of its other words before being checked against the stored text. The
+// `ConcurrentMultiMap` holds a single `PostingList` per key, packing its postings into one buffer
+// sorted by id, with ids and positions written as the gaps between them.
+//
+// A database with storage keeps only the postings of recent changes in the `ConcurrentMultiMap`.
+// Once there are enough of them, or a snapshot is taken, they are flushed into an immutable
+// segment file, sorted by key and memory mapped for reading, and lookups merge the postings in
+// memory with those in every segment. Segments
+// can't be changed, so the documents deleted or replaced after a segment was written are masked
+// out of it instead, and merging segments drops their postings for good.
+
+/// An entry of the reverse index: the document `id` contains the key starting at each of the
+/// word offsets in `positions`, which are in ascending order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Posting {
+    pub id: usize,
+    pub positions: Vec<usize>,
+}
+
+impl Posting {
+    /// The number of times the key occurs in the document
+    pub fn count(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+/// The postings of one key as kept in memory, packed into a single buffer in ascending order
+/// of id. Each posting is written as the gap from the previous id, as a variable-length integer,
+/// followed by its positions as a sorted list.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct PostingList {
+    bytes: Vec<u8>,
+    /// The id of the last posting in the list
+    last: usize,
+}
+
+impl PostingList {
+    /// Add the posting of the document `id`, replacing any it already has. Postings for ids past
+    /// the end of the list are appended, and any others rewrite the list.
+    fn insert(&mut self, id: usize, positions: &[usize]) {
+        if self.bytes.is_empty() || id > self.last {
+            varint::write_varint(&mut self.bytes, (id - self.last) as u64);
+            varint::write_sorted(&mut self.bytes, positions);
+            self.last = id;
+            return;
+        }
+        let mut postings = self.unpack();
+        postings.retain(|posting| posting.id != id);
+        let index = postings.partition_point(|posting| posting.id < id);
+        let positions = positions.to_vec();
+        postings.insert(index, Posting { id, positions });
+        self.pack(&postings);
+    }
+
+    /// Remove the posting of the document `id`, if there is one
+    fn remove(&mut self, id: usize) {
+        let mut postings = self.unpack();
+        let before = postings.len();
+        postings.retain(|posting| posting.id != id);
+        if postings.len() != before {
+            self.pack(&postings);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn pack(&mut self, postings: &[Posting]) {
+        self.bytes.clear();
+        self.last = 0;
+        for posting in postings {
+            self.insert(posting.id, &posting.positions);
+        }
+    }
+
+    fn unpack(&self) -> Vec<Posting> {
+        let mut postings = Vec::new();
+        let mut reader = &self.bytes[..];
+        let mut id = 0;
+        while !reader.is_empty() {
+            let (Some(gap), Some(positions)) = (
+                varint::read_varint(&mut reader),
+                varint::read_sorted(&mut reader),
+            ) else {
+                break;
+            };
+            id += gap as usize;
+            postings.push(Posting { id, positions });
+        }
+        postings
+    }
+}
+
+/// Bibliographic details of a published document
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub title: String,
+    pub author: String,
+    pub year: Option<u32>,
+}
+
+/// What the database knows about a document besides its text
+#[derive(Clone, Debug)]
+struct DocumentInfo {
+    metadata: Option<Metadata>,
+    /// Whether the document has been deleted
+    deleted: bool,
+    /// The number of words in the body of the document, stop words included
+    length: usize,
+    /// The hash of the full text of the document
+    hash: u64,
+    /// The MinHash signature of the words of the document
+    signature: Vec<u64>,
+    /// Whether the postings of the document are in the segments rather than in memory
+    flushed: bool,
+}
+
+/// A segment of the reverse index along with the documents masked out of it
+struct IndexSegment {
+    /// The number the segment is stored under
+    number: u64,
+    segment: Segment,
+    /// The documents deleted or replaced since the segment was written
+    masked: RwLock<HashSet<usize>>,
+}
+
+impl IndexSegment {
+    fn new(number: u64, segment: Segment, masked: HashSet<usize>) -> Self {
+        Self {
+            number,
+            segment,
+            masked: RwLock::new(masked),
+        }
+    }
+}
 
 /// A document database that allows clients to publish documents and
 /// search for documents containing specific words.
 pub struct Database {
-    /// A map from words to the set of documents that contain them
-    reverse_index: ConcurrentMultiMap<String, usize>,
-    /// A store of all documents in the database
-    blob_store: Mutex<Vec<String>>,
+    /// A map from words to the set of documents that contain them, for the postings not yet
+    /// flushed to a segment
+    reverse_index: ConcurrentMultiMap<String, PostingList>,
+    /// The segments holding the postings flushed from `reverse_index`
+    segments: RwLock<Vec<Arc<IndexSegment>>>,
+    /// The number the next segment will be stored under
+    next_segment: AtomicU64,
+    /// The number of postings added to `reverse_index` since it was last flushed
+    unflushed: AtomicUsize,
+    /// How many postings `reverse_index` may hold before it is flushed to a segment
+    flush_threshold: usize,
+    /// Held while segments are being merged, so that only one merge runs at a time
+    merging: Mutex<()>,
+    /// Every word of the reverse index, in sorted order, leaving out the n-grams
+    vocabulary: Vocabulary,
+    /// The text of every document in the database, compressed in blocks and split into shards by
+    /// id, with deleted ones left out. Reading it never waits on a change being made.
+    blob_store: ShardedBlobStore,
+    /// Held while the database is changed, so that changes are made one at a time and a snapshot
+    /// never sees one half made
+    changes: Mutex<()>,
+    /// Metadata of every document, indexed like `blob_store`
+    documents: RwLock<Vec<DocumentInfo>>,
+    /// The total number of words in the documents published in each year
+    year_totals: Mutex<BTreeMap<u32, usize>>,
+    /// The length of the longest word sequence stored in the reverse index
+    max_ngram: usize,
+    /// Splits documents and queries into the words stored in the reverse index
+    tokenizer: Box<dyn Tokenizer>,
+    /// Lowercase words that are too common to be worth indexing
+    stop_words: HashSet<String>,
+    /// What `publish_checked` does with a document that is already published
+    duplicate_policy: DuplicatePolicy,
+    /// How similar two documents must be for `publish_checked` to report them as near duplicates
+    near_duplicate_threshold: f64,
+    /// The snapshot and write-ahead log that every change is recorded in, if the database has
+    /// storage
+    storage: Option<Mutex<Storage>>,
+}
+
+/// What `Database::publish_checked` does with a document whose exact text is already published
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Publish it again under a new id
+    #[default]
+    Allow,
+    /// Don't publish it, and return the id of the existing copy instead
+    ReturnExisting,
+    /// Don't publish it, and fail with the id of the existing copy
+    Reject,
+}
+
+/// A document published by `Database::publish_checked`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Published {
+    /// The id of the document
+    pub id: usize,
+    /// The other documents that share most of their text with it, with their estimated
+    /// similarity, most similar first
+    pub near_duplicates: Vec<(usize, f64)>,
+}
+
+/// The error returned when a document is rejected for already being published
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DuplicateError {
+    /// The id of the document with the same text
+    pub existing: usize,
+}
+
+impl fmt::Display for DuplicateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "already published as document {}", self.existing)
+    }
+}
+
+impl std::error::Error for DuplicateError {}
+
+/// The error returned when `Database::publish_checked` doesn't publish a document
+#[derive(Debug)]
+pub enum PublishError {
+    /// Its exact text is already published and the duplicate policy rejects it
+    Duplicate(DuplicateError),
+    /// The change couldn't be recorded in the database's storage
+    Io(io::Error),
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::Duplicate(e) => e.fmt(f),
+            PublishError::Io(e) => write!(f, "failed to write to the log: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+impl From<io::Error> for PublishError {
+    fn from(e: io::Error) -> Self {
+        PublishError::Io(e)
+    }
+}
+
+/// How `Database::timeline` turns occurrences into a value per year
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimelineOptions {
+    /// Divide the occurrences in each year by the number of words published that year
+    pub normalize: bool,
+    /// Average each year with the years up to this many years before and after it
+    pub smoothing: u32,
+}
+
+impl Default for TimelineOptions {
+    fn default() -> Self {
+        Self {
+            normalize: true,
+            smoothing: 0,
+        }
+    }
+}
+
+/// An occurrence of a word or phrase in a document along with the words around it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConcordanceLine {
+    /// The document the occurrence is in
+    pub id: usize,
+    /// The word offset at which the occurrence starts
+    pub offset: usize,
+    /// The words before the occurrence, as written in the document
+    pub left: String,
+    /// The occurrence itself, as written in the document
+    pub keyword: String,
+    /// The words after the occurrence, as written in the document
+    pub right: String,
+}
+
+/// A short excerpt of a ranked document around the words of the query
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snippet {
+    /// The document the excerpt is from
+    pub id: usize,
+    /// The score of the document, as returned by `Database::ranked_search`
+    pub score: f64,
+    /// The excerpt, with line breaks replaced by spaces
+    pub text: String,
+    /// The byte ranges in `text` of the words of the query, in order
+    pub highlights: Vec<Range<usize>>,
+}
+
+/// Figures describing the whole archive
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CorpusStats {
+    /// The number of documents
+    pub documents: usize,
+    /// The number of words in all documents, stop words included
+    pub tokens: usize,
+    /// The number of distinct indexed words
+    pub vocabulary: usize,
+    /// The average number of words in a document
+    pub average_length: f64,
+    /// The most frequent indexed words with their number of occurrences, most frequent first
+    pub top_terms: Vec<(String, usize)>,
+}
+
+/// Figures describing a single document
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DocStats {
+    /// The document described
+    pub id: usize,
+    /// The number of words in the document, stop words included
+    pub tokens: usize,
+    /// The number of distinct indexed words in the document
+    pub distinct: usize,
+    /// The most frequent indexed words with their number of occurrences, most frequent first
+    pub top_terms: Vec<(String, usize)>,
+}
+
+/// How often an indexed word occurs in the archive
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TermFrequency {
+    pub term: String,
+    /// The number of documents containing the word
+    pub documents: usize,
+    /// The number of times the word occurs across all documents
+    pub occurrences: usize,
+}
+
+/// A word that occurs near another word, with how strongly the two are associated
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Collocation {
+    /// The word occurring nearby
+    pub word: String,
+    /// The number of times it occurs within the window of the other word
+    pub count: usize,
+    /// Pointwise mutual information: the base 2 log of how much more often the word occurs
+    /// within the window than in the corpus as a whole
+    pub pmi: f64,
+    /// Dunning's log-likelihood ratio, which unlike PMI is not inflated for rare words
+    pub log_likelihood: f64,
+}
+
+/// The measure of association used to rank collocations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Association {
+    /// Rank by pointwise mutual information
+    Pmi,
+    /// Rank by Dunning's log-likelihood ratio
+    #[default]
+    LogLikelihood,
+}
+
+/// How `Database::ranked_search` scores a document against the terms of a query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scoring {
+    /// The number of times each term occurs in the document, weighted by `ln(1 + N / df)`, where
+    /// `N` is the number of documents and `df` the number containing the term
+    TfIdf,
+    /// Okapi BM25, where `k1` controls how quickly repeated occurrences of a term stop adding to
+    /// the score and `b` how strongly scores are normalized by the length of the document
+    Bm25 { k1: f64, b: f64 },
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring::Bm25 { k1: 1.2, b: 0.75 }
+    }
 }
 
 const BUCKETS: usize = 128;
 
+/// The length of the longest word sequence indexed unless configured otherwise
+pub const DEFAULT_MAX_NGRAM: usize = 1;
+
+/// The estimated fraction of shared text above which documents are reported as near duplicates
+/// unless configured otherwise
+pub const DEFAULT_NEAR_DUPLICATE_THRESHOLD: f64 = 0.8;
+
+/// The number of postings held in memory before they are flushed to a segment unless configured
+/// otherwise
+pub const DEFAULT_FLUSH_THRESHOLD: usize = 1 << 20;
+
+/// The number of segments above which `Database::merge_segments` merges them into one
+pub const MAX_SEGMENTS: usize = 8;
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Database {
     // TODO:
     // Create a new empty archive. The map should have `BUCKETS` buckets.
     pub fn new() -> Self {
         Self {
             reverse_index: ConcurrentMultiMap::new(BUCKETS),
-            blob_store: Mutex::new(Vec::new()),
+            segments: RwLock::new(Vec::new()),
+            next_segment: AtomicU64::new(0),
+            unflushed: AtomicUsize::new(0),
+            flush_threshold: DEFAULT_FLUSH_THRESHOLD,
+            merging: Mutex::new(()),
+            vocabulary: Vocabulary::new(),
+            blob_store: ShardedBlobStore::new(),
+            changes: Mutex::new(()),
+            documents: RwLock::new(Vec::new()),
+            year_totals: Mutex::new(BTreeMap::new()),
+            max_ngram: DEFAULT_MAX_NGRAM,
+            tokenizer: Box::new(WhitespaceTokenizer),
+            stop_words: HashSet::new(),
+            duplicate_policy: DuplicatePolicy::default(),
+            near_duplicate_threshold: DEFAULT_NEAR_DUPLICATE_THRESHOLD,
+            storage: None,
+        }
+    }
+
+    /// Index every sequence of up to `max_ngram` words when publishing. Values below 1 are
+    /// treated as 1.
+    pub fn with_max_ngram(mut self, max_ngram: usize) -> Self {
+        self.max_ngram = max_ngram.max(1);
+        self
+    }
+
+    /// The length of the longest word sequence stored in the reverse index
+    pub fn max_ngram(&self) -> usize {
+        self.max_ngram
+    }
+
+    /// Split documents and queries into words with `tokenizer` instead of on whitespace
+    pub fn with_tokenizer<T: Tokenizer + 'static>(mut self, tokenizer: T) -> Self {
+        self.tokenizer = Box::new(tokenizer);
+        self
+    }
+
+    /// Leave the words in `stop_words` out of the reverse index. Words are matched regardless of
+    /// case.
+    pub fn with_stop_words(mut self, stop_words: HashSet<String>) -> Self {
+        self.stop_words = stop_words.iter().map(|w| w.to_lowercase()).collect();
+        self
+    }
+
+    /// Handle documents that are already published according to `policy` in `publish_checked`
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Report documents sharing at least this fraction of their text as near duplicates in
+    /// `publish_checked`
+    pub fn with_near_duplicate_threshold(mut self, threshold: f64) -> Self {
+        self.near_duplicate_threshold = threshold;
+        self
+    }
+
+    /// Flush the postings in memory to a segment once there are `threshold` of them, if the
+    /// database has storage
+    pub fn with_flush_threshold(mut self, threshold: usize) -> Self {
+        self.flush_threshold = threshold;
+        self
+    }
+
+    /// Keep the database in the directory `dir`, creating it if needed. The latest snapshot
+    /// stored there is loaded and the changes made since are published again, so this should
+    /// come after every other option that affects indexing.
+    pub fn with_storage<P: AsRef<Path>>(mut self, dir: P) -> io::Result<Self> {
+        let (mut storage, snapshot, records) = Storage::open(dir.as_ref())?;
+        // The manifest lists the segments as of the last flush, which hold the postings of the
+        // snapshot and of the changes logged before the flush. If it was indexed with different
+        // options, it is ignored and the log is indexed again.
+        let mut manifest = None;
+        if let Some((contents, covered)) = storage.take_manifest() {
+            let mut reader = &contents[..];
+            if read_u64(&mut reader) == Some(self.fingerprint()) {
+                let segments = self
+                    .read_index(&mut reader)
+                    .filter(|_| reader.is_empty())
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "the index manifest is corrupt")
+                    })?;
+                manifest = Some((segments, covered));
+            }
+        }
+        let mut segments = Vec::new();
+        if let Some(snapshot) = snapshot {
+            segments = self.restore(&snapshot, manifest.is_some()).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "the snapshot is corrupt")
+            })?;
         }
+        let mut covered = 0;
+        if let Some((listed, flushed)) = manifest {
+            segments = listed;
+            covered = flushed;
+        }
+        let mut keys = BTreeSet::new();
+        for (number, masked) in segments {
+            let segment = Segment::open(&storage.segment_path(number))?;
+            for (key, postings) in segment.iter() {
+                if is_word(&key) && postings.iter().any(|p| !masked.contains(&p.id)) {
+                    keys.insert(key);
+                }
+            }
+            let segment = IndexSegment::new(number, segment, masked);
+            self.segments.get_mut().unwrap().push(Arc::new(segment));
+        }
+        self.vocabulary.extend(keys);
+        for (i, record) in records.into_iter().enumerate() {
+            if i < covered {
+                self.apply_flushed(record)?;
+            } else {
+                self.apply(record)?;
+            }
+        }
+        self.storage = Some(Mutex::new(storage));
+        Ok(self)
+    }
+
+    /// Write a snapshot of the whole database to its storage and start a new log, so that the
+    /// database can be loaded without replaying every change made so far. The postings in memory
+    /// are flushed to a new segment first. Searches carry on while the snapshot is written, but
+    /// changes wait for it to finish. Return the generation of the new snapshot.
+    pub fn snapshot(&self) -> io::Result<u64> {
+        let _changes = self.changes.lock().unwrap();
+        self.snapshot_locked()
+    }
+
+    /// Take a snapshot. The caller must hold the `changes` lock, so that nothing changes while it
+    /// is written.
+    fn snapshot_locked(&self) -> io::Result<u64> {
+        let Some(storage) = &self.storage else {
+            return Err(io::Error::other("the database has no storage"));
+        };
+        let mut storage = storage.lock().unwrap();
+        self.flush(&storage)?;
+        let generation = storage.snapshot(|out| self.write_snapshot(out))?;
+        let numbers: Vec<u64> = self
+            .segments
+            .read()
+            .unwrap()
+            .iter()
+            .map(|s| s.number)
+            .collect();
+        storage.remove_unused_segments(&numbers)?;
+        Ok(generation)
+    }
+
+    /// Flush the postings in memory to a new segment if they have reached the flush threshold,
+    /// and record the segment in the log and the index manifest. Unlike a snapshot, this doesn't
+    /// write out the documents. The caller must hold the `changes` lock.
+    fn flush_if_full(&self) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        if self.unflushed.load(Ordering::SeqCst) < self.flush_threshold {
+            return;
+        }
+        let mut storage = storage.lock().unwrap();
+        let flushed = self.flush(&storage).and_then(|number| match number {
+            Some(number) => storage.flush(number, |out| self.write_manifest(out)),
+            None => Ok(()),
+        });
+        if let Err(e) = flushed {
+            eprintln!("Failed to flush the index: {}", e);
+        }
+    }
+
+    /// Write the postings in memory to a new segment and clear them, and return the number the
+    /// segment is stored under, or `None` if there were no postings to write. The caller must
+    /// hold the `changes` lock.
+    fn flush(&self, storage: &Storage) -> io::Result<Option<u64>> {
+        let mut entries: BTreeMap<String, Vec<Posting>> = BTreeMap::new();
+        self.reverse_index.for_each(|key, list| {
+            entries.insert(key.clone(), list.unpack());
+        });
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let number = self.next_segment.fetch_add(1, Ordering::SeqCst);
+        let path = storage.segment_path(number);
+        Segment::write(&path, entries)?;
+        let segment = IndexSegment::new(number, Segment::open(&path)?, HashSet::new());
+        // The segment is added before the postings in memory are cleared, and lookups read the
+        // postings in memory first, so that a lookup running meanwhile sees every posting
+        self.segments.write().unwrap().push(Arc::new(segment));
+        self.reverse_index.clear();
+        self.unflushed.store(0, Ordering::SeqCst);
+        for info in self.documents.write().unwrap().iter_mut() {
+            info.flushed = true;
+        }
+        Ok(Some(number))
+    }
+
+    /// Merge the segments into one if there are more than `MAX_SEGMENTS` of them, dropping the
+    /// postings of deleted and replaced documents, and write an index manifest to record the
+    /// merged segment. Searches and changes carry on while the merged segment is written. Return
+    /// whether the segments were merged.
+    pub fn merge_segments(&self) -> io::Result<bool> {
+        let Some(storage) = &self.storage else {
+            return Ok(false);
+        };
+        let Ok(_merging) = self.merging.try_lock() else {
+            return Ok(false);
+        };
+        let inputs = self.segments.read().unwrap().clone();
+        if inputs.len() <= MAX_SEGMENTS {
+            return Ok(false);
+        }
+        let masks: Vec<HashSet<usize>> = inputs
+            .iter()
+            .map(|input| input.masked.read().unwrap().clone())
+            .collect();
+        let number = self.next_segment.fetch_add(1, Ordering::SeqCst);
+        // The merged segment isn't listed anywhere until it is swapped in below, so a snapshot
+        // taken meanwhile must be told not to remove it
+        let path = storage.lock().unwrap().reserve_segment(number);
+        let sources: Vec<(&Segment, &HashSet<usize>)> = inputs
+            .iter()
+            .zip(&masks)
+            .map(|(input, masked)| (&input.segment, masked))
+            .collect();
+        let segment = match Segment::write(&path, segment::merge(&sources))
+            .and_then(|()| Segment::open(&path))
+        {
+            Ok(segment) => segment,
+            Err(e) => {
+                storage.lock().unwrap().release_segment(number);
+                return Err(e);
+            }
+        };
+
+        let _changes = self.changes.lock().unwrap();
+        // The documents deleted or replaced while merging still have postings in the merged
+        // segment, so they stay masked
+        let mut masked = HashSet::new();
+        for (input, before) in inputs.iter().zip(&masks) {
+            masked.extend(input.masked.read().unwrap().difference(before));
+        }
+        {
+            let mut segments = self.segments.write().unwrap();
+            segments.retain(|s| inputs.iter().all(|input| input.number != s.number));
+            segments.insert(0, Arc::new(IndexSegment::new(number, segment, masked)));
+        }
+        let mut storage = storage.lock().unwrap();
+        storage.release_segment(number);
+        storage.write_manifest(|out| self.write_manifest(out))?;
+        let numbers: Vec<u64> = self
+            .segments
+            .read()
+            .unwrap()
+            .iter()
+            .map(|s| s.number)
+            .collect();
+        storage.remove_unused_segments(&numbers)?;
+        Ok(true)
+    }
+
+    /// The number of segments the reverse index is split into besides the postings in memory
+    pub fn segment_count(&self) -> usize {
+        self.segments.read().unwrap().len()
+    }
+
+    /// Write the documents, year totals and segments to `out`. The caller must hold the
+    /// `changes` lock, so that nothing changes while they are written.
+    fn write_snapshot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        write_u64(&mut bytes, self.fingerprint());
+        write_usize(&mut bytes, self.blob_store.len());
+        let documents = self.documents.read().unwrap();
+        for (id, info) in documents.iter().enumerate() {
+            let doc = self.blob_store.get(id);
+            write_bool(&mut bytes, doc.is_some());
+            if let Some(doc) = doc {
+                write_string(&mut bytes, &doc);
+            }
+            write_metadata(&mut bytes, &info.metadata);
+            write_bool(&mut bytes, info.deleted);
+            write_usize(&mut bytes, info.length);
+            write_u64(&mut bytes, info.hash);
+            write_u32(&mut bytes, info.signature.len() as u32);
+            for &hash in &info.signature {
+                write_u64(&mut bytes, hash);
+            }
+            out.write_all(&bytes)?;
+            bytes.clear();
+        }
+        drop(documents);
+
+        let year_totals = self.year_totals.lock().unwrap().clone();
+        write_u32(&mut bytes, year_totals.len() as u32);
+        for (year, total) in year_totals {
+            write_u32(&mut bytes, year);
+            write_usize(&mut bytes, total);
+        }
+
+        self.write_index(&mut bytes);
+        out.write_all(&bytes)
+    }
+
+    /// Write an index manifest listing the segments to `out`. The caller must hold the `changes`
+    /// lock.
+    fn write_manifest<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        write_u64(&mut bytes, self.fingerprint());
+        self.write_index(&mut bytes);
+        out.write_all(&bytes)
+    }
+
+    /// Append the number of the next segment and every segment with the documents masked out of
+    /// it to `bytes`
+    fn write_index(&self, bytes: &mut Vec<u8>) {
+        write_u64(bytes, self.next_segment.load(Ordering::SeqCst));
+        let segments = self.segments.read().unwrap();
+        write_u32(bytes, segments.len() as u32);
+        for segment in segments.iter() {
+            write_u64(bytes, segment.number);
+            let masked = segment.masked.read().unwrap();
+            write_u32(bytes, masked.len() as u32);
+            for &id in masked.iter() {
+                write_usize(bytes, id);
+            }
+        }
+    }
+
+    /// Read what `write_index` wrote, setting the number of the next segment and returning the
+    /// segments to open along with the documents masked out of each
+    fn read_index(&self, reader: &mut &[u8]) -> Option<Vec<(u64, HashSet<usize>)>> {
+        self.next_segment.store(read_u64(reader)?, Ordering::SeqCst);
+        let mut segments = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            let number = read_u64(reader)?;
+            let masked = (0..read_u32(reader)?)
+                .map(|_| read_usize(reader))
+                .collect::<Option<HashSet<_>>>()?;
+            segments.push((number, masked));
+        }
+        Some(segments)
+    }
+
+    /// Load a snapshot written by `write_snapshot` into this empty database, and return the
+    /// segments to open along with the documents masked out of each, or `None` if the snapshot
+    /// is invalid. If the snapshot was indexed with different options, its documents are
+    /// indexed again instead of opening its segments, unless `indexed` says their postings are
+    /// already in the segments of an index manifest.
+    fn restore(&self, mut reader: &[u8], indexed: bool) -> Option<Vec<(u64, HashSet<usize>)>> {
+        let reindex = read_u64(&mut reader)? != self.fingerprint() && !indexed;
+        for _ in 0..read_usize(&mut reader)? {
+            let doc = if read_bool(&mut reader)? {
+                Some(read_string(&mut reader)?)
+            } else {
+                None
+            };
+            let metadata = read_metadata(&mut reader)?;
+            let deleted = read_bool(&mut reader)?;
+            let length = read_usize(&mut reader)?;
+            let hash = read_u64(&mut reader)?;
+            let signature = (0..read_u32(&mut reader)?)
+                .map(|_| read_u64(&mut reader))
+                .collect::<Option<Vec<_>>>()?;
+            match doc {
+                Some(doc) if reindex => {
+                    self.insert(doc, metadata).ok()?;
+                }
+                doc => {
+                    self.documents.write().unwrap().push(DocumentInfo {
+                        metadata,
+                        deleted,
+                        length,
+                        hash,
+                        signature,
+                        flushed: true,
+                    });
+                    self.blob_store.push(doc);
+                }
+            }
+        }
+
+        let mut year_totals = BTreeMap::new();
+        for _ in 0..read_u32(&mut reader)? {
+            let year = read_u32(&mut reader)?;
+            year_totals.insert(year, read_usize(&mut reader)?);
+        }
+        if !reindex {
+            *self.year_totals.lock().unwrap() = year_totals;
+        }
+
+        let mut segments = self.read_index(&mut reader)?;
+        if reindex {
+            segments.clear();
+        }
+        reader.is_empty().then_some(segments)
+    }
+
+    /// A hash of the options that affect indexing, which tells whether the reverse index in a
+    /// snapshot can be loaded as it is. The tokenizer is told apart by how it splits a sample.
+    fn fingerprint(&self) -> u64 {
+        let mut stop_words: Vec<&String> = self.stop_words.iter().collect();
+        stop_words.sort();
+        let sample = self
+            .tokenizer
+            .tokenize("Call me Ishmael. Some years ago, in 1851!");
+        let options = format!("{}|{:?}|{:?}", self.max_ngram, stop_words, sample);
+        dedup::content_hash(&options)
+    }
+
+    /// Redo a change read back from storage
+    fn apply(&self, record: Record) -> io::Result<()> {
+        let applied = match record {
+            Record::Publish { id, doc, metadata } => {
+                let _changes = self.changes.lock().unwrap();
+                id == self.blob_store.len() && self.insert(doc, metadata)? == id
+            }
+            Record::Delete { id } => self.delete(id)?,
+            Record::Replace { id, doc } => self.replace(id, doc)?,
+            Record::Flush { .. } => true,
+        };
+        check_applied(applied)
+    }
+
+    /// Redo a change read back from storage whose postings were flushed to a segment, by
+    /// restoring the text and description of the document without indexing it
+    fn apply_flushed(&self, record: Record) -> io::Result<()> {
+        let applied = match record {
+            Record::Publish { id, doc, metadata } => {
+                if id != self.blob_store.len() {
+                    return check_applied(false);
+                }
+                let (_, body) = split_header(&doc);
+                let words = self.tokenizer.tokenize(body);
+                if let Some(year) = metadata.as_ref().and_then(|m| m.year) {
+                    *self.year_totals.lock().unwrap().entry(year).or_default() += words.len();
+                }
+                self.documents.write().unwrap().push(DocumentInfo {
+                    metadata,
+                    deleted: false,
+                    length: words.len(),
+                    hash: dedup::content_hash(&doc),
+                    signature: dedup::signature(&words),
+                    flushed: true,
+                });
+                self.blob_store.push(Some(doc));
+                true
+            }
+            Record::Delete { id } => {
+                let deleted = self.blob_store.take(id).is_some();
+                if deleted {
+                    self.forget(id);
+                }
+                deleted
+            }
+            Record::Replace { id, doc } => {
+                let replaced = self.blob_store.contains(id);
+                if replaced {
+                    let (metadata, body) = split_header(&doc);
+                    let words = self.tokenizer.tokenize(body);
+                    self.redescribe(id, &doc, metadata, &words);
+                    self.documents.write().unwrap()[id].flushed = true;
+                    self.blob_store.set(id, doc);
+                }
+                replaced
+            }
+            Record::Flush { .. } => true,
+        };
+        check_applied(applied)
+    }
+
+    /// Record a change in the write-ahead log, if the database has storage. If it can't be
+    /// recorded, the change must not be made.
+    fn append(&self, record: impl FnOnce() -> Record) -> io::Result<()> {
+        match &self.storage {
+            Some(storage) => storage.lock().unwrap().append(&record()),
+            None => Ok(()),
+        }
+    }
+
+    fn is_stop_word(&self, term: &str) -> bool {
+        !self.stop_words.is_empty() && self.stop_words.contains(&term.to_lowercase())
     }
 
     // TODO:
@@ -35,27 +917,1018 @@ impl Database {
     //    whitespace is sufficient. It is up to you whether to also perform transformations like
     //    converting to lowercase or removing numerals.
     // 3. Add the document to the blob store
-    pub fn publish(&self, doc: String) -> usize {
-        let mut store = self.blob_store.lock().unwrap();
-        let id = store.len();
-        for word in doc.split_whitespace() {
-            self.reverse_index.set(word.to_string(), id);
+    //
+    // A `[Title by Author Year]` header on the first line is recorded as the document's metadata
+    // rather than indexed.
+    //
+    // The document is published even if the same text already is; see `publish_checked`. Fails
+    // if the database has storage and the change can't be recorded in it.
+    pub fn publish(&self, doc: String) -> io::Result<usize> {
+        let (metadata, _) = split_header(&doc);
+        let _changes = self.changes.lock().unwrap();
+        self.insert(doc, metadata)
+    }
+
+    /// Publish a document with explicitly given metadata, which takes precedence over any header
+    /// in the document itself
+    pub fn publish_with_metadata(&self, doc: String, metadata: Metadata) -> io::Result<usize> {
+        let _changes = self.changes.lock().unwrap();
+        self.insert(doc, Some(metadata))
+    }
+
+    /// Publish a document, with `metadata` taking precedence over any header in the document,
+    /// unless its exact text is already published and the duplicate policy says otherwise.
+    /// Also report the published documents that are near duplicates of it.
+    pub fn publish_checked(
+        &self,
+        doc: String,
+        metadata: Option<Metadata>,
+    ) -> Result<Published, PublishError> {
+        let metadata = metadata.or_else(|| split_header(&doc).0);
+        let _changes = self.changes.lock().unwrap();
+        if self.duplicate_policy != DuplicatePolicy::Allow {
+            if let Some(existing) = self.find_duplicate(&doc) {
+                return match self.duplicate_policy {
+                    DuplicatePolicy::Reject => {
+                        Err(PublishError::Duplicate(DuplicateError { existing }))
+                    }
+                    _ => Ok(Published {
+                        id: existing,
+                        near_duplicates: Vec::new(),
+                    }),
+                };
+            }
+        }
+        let id = self.insert(doc, metadata)?;
+        // Near duplicates are only looked for here, since comparing against every document in
+        // `insert` would make loading a database take quadratic time
+        Ok(Published {
+            id,
+            near_duplicates: self.near_duplicates(id),
+        })
+    }
+
+    /// Get the id of a published document whose text is exactly `doc`
+    fn find_duplicate(&self, doc: &str) -> Option<usize> {
+        let hash = dedup::content_hash(doc);
+        let documents = self.documents.read().unwrap();
+        (0..documents.len()).find(|&id| {
+            documents[id].hash == hash && self.blob_store.get(id).as_deref() == Some(doc)
+        })
+    }
+
+    /// Publish a document. The caller must hold the `changes` lock. The text is stored last, so
+    /// the document can't be retrieved until it is fully indexed.
+    fn insert(&self, doc: String, metadata: Option<Metadata>) -> io::Result<usize> {
+        // The id is only handed out once the change is recorded, so that a failed write leaves
+        // no gap in the ids
+        self.append(|| Record::Publish {
+            id: self.blob_store.len(),
+            doc: doc.clone(),
+            metadata: metadata.clone(),
+        })?;
+        let id = self.blob_store.reserve();
+        let (_, body) = split_header(&doc);
+        let words = self.tokenizer.tokenize(body);
+        let signature = dedup::signature(&words);
+        if let Some(year) = metadata.as_ref().and_then(|m| m.year) {
+            *self.year_totals.lock().unwrap().entry(year).or_default() += words.len();
         }
-        store.push(doc);
-        id
+        // The document is described before any of its postings are added, so that a search
+        // finding one of them can always look the document up
+        self.documents.write().unwrap().push(DocumentInfo {
+            metadata,
+            deleted: false,
+            length: words.len(),
+            hash: dedup::content_hash(&doc),
+            signature,
+            flushed: false,
+        });
+        let positions = self.index_keys(&words);
+        self.vocabulary
+            .extend(positions.keys().filter(|key| is_word(key)).cloned());
+        self.unflushed.fetch_add(positions.len(), Ordering::SeqCst);
+        for (key, positions) in positions {
+            self.add_posting(key, id, &positions);
+        }
+        self.blob_store.insert(id, Some(doc));
+        self.flush_if_full();
+        Ok(id)
+    }
+
+    /// Get the other documents whose signature is at least `near_duplicate_threshold` similar to
+    /// that of the document with the given id, most similar first
+    fn near_duplicates(&self, id: usize) -> Vec<(usize, f64)> {
+        let documents = self.documents.read().unwrap();
+        let signature = &documents[id].signature;
+        let mut similar: Vec<(usize, f64)> = documents
+            .iter()
+            .enumerate()
+            .filter(|&(other, info)| other != id && !info.deleted)
+            .map(|(id, info)| (id, dedup::similarity(signature, &info.signature)))
+            .filter(|&(_, similarity)| similarity >= self.near_duplicate_threshold)
+            .collect();
+        similar.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        similar
+    }
+
+    /// Get every key of the reverse index that occurs in `words`, with the positions at which it
+    /// occurs
+    fn index_keys(&self, words: &[String]) -> HashMap<String, Vec<usize>> {
+        let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+        let indexed: Vec<bool> = words.iter().map(|w| !self.is_stop_word(w)).collect();
+        for n in 1..=self.max_ngram {
+            for (start, ngram) in words.windows(n).enumerate() {
+                if indexed[start..start + n].iter().all(|&i| i) {
+                    positions.entry(ngram.join(" ")).or_default().push(start);
+                }
+            }
+        }
+        positions
+    }
+
+    /// Delete the document with the given id, removing it from the reverse index and dropping
+    /// its text. Its id is never reused. Return false if the id is invalid or the document was
+    /// already deleted, and fail if the change can't be recorded in storage.
+    pub fn delete(&self, id: usize) -> io::Result<bool> {
+        let _changes = self.changes.lock().unwrap();
+        if !self.blob_store.contains(id) {
+            return Ok(false);
+        }
+        self.append(|| Record::Delete { id })?;
+        let Some(doc) = self.blob_store.take(id) else {
+            return Ok(false);
+        };
+        if self.documents.read().unwrap()[id].flushed {
+            self.mask(id);
+        }
+        let (_, body) = split_header(&doc);
+        let words = self.tokenizer.tokenize(body);
+        for key in self.index_keys(&words).into_keys() {
+            self.remove_posting(key.clone(), id);
+            if is_word(&key) && self.postings(&key).is_empty() {
+                self.vocabulary.remove(&key);
+            }
+        }
+        self.forget(id);
+        Ok(true)
+    }
+
+    /// Add the posting of the document `id` to the postings of `key` in memory, replacing any
+    /// it already has there
+    fn add_posting(&self, key: String, id: usize, positions: &[usize]) {
+        self.reverse_index.update(key, |list| {
+            list.get_or_insert_with(PostingList::default)
+                .insert(id, positions);
+        });
+    }
+
+    /// Remove the posting of the document `id` from the postings of `key` in memory
+    fn remove_posting(&self, key: String, id: usize) {
+        self.reverse_index.update(key, |list| {
+            if let Some(postings) = list {
+                postings.remove(id);
+                if postings.is_empty() {
+                    *list = None;
+                }
+            }
+        });
+    }
+
+    /// Mark the document with the given id as deleted in its description and the year totals
+    fn forget(&self, id: usize) {
+        let mut documents = self.documents.write().unwrap();
+        let info = &mut documents[id];
+        if let Some(year) = info.metadata.as_ref().and_then(|m| m.year) {
+            let mut totals = self.year_totals.lock().unwrap();
+            if let Some(total) = totals.get_mut(&year) {
+                *total -= info.length;
+            }
+        }
+        info.deleted = true;
+        info.length = 0;
+        info.signature = Vec::new();
+    }
+
+    /// Replace the text of the document with the given id by `doc`, keeping its id. Unless the
+    /// postings of the document have been flushed to a segment, only the reverse index keys
+    /// whose postings differ between the old and new text are updated. The new postings are
+    /// added before the old ones are removed or masked, so searches running at the same time
+    /// find the document under every key of the new text, but may briefly still find it under
+    /// keys that only the old text contains. A `[Title by Author Year]` header in `doc` replaces
+    /// the document's metadata, which is otherwise kept. Return false if the id is invalid or the
+    /// document was deleted, and fail if the change can't be recorded in storage.
+    pub fn replace(&self, id: usize, doc: String) -> io::Result<bool> {
+        let _changes = self.changes.lock().unwrap();
+        let Some(old) = self.blob_store.get(id) else {
+            return Ok(false);
+        };
+        self.append(|| Record::Replace {
+            id,
+            doc: doc.clone(),
+        })?;
+        let (_, old_body) = split_header(&old);
+        let old_keys = self.index_keys(&self.tokenizer.tokenize(old_body));
+        let (metadata, body) = split_header(&doc);
+        let words = self.tokenizer.tokenize(body);
+        let new_keys = self.index_keys(&words);
+
+        // The postings of a flushed document can't be updated in its segment, so they are all
+        // added again in memory and then masked. Lookups keep the postings in memory over those
+        // in segments, so the new postings win for the keys both texts contain.
+        let flushed = std::mem::take(&mut self.documents.write().unwrap()[id].flushed);
+        let unchanged = if flushed {
+            HashMap::new()
+        } else {
+            old_keys.clone()
+        };
+        let added: Vec<String> = new_keys
+            .keys()
+            .filter(|key| is_word(key) && !old_keys.contains_key(*key))
+            .cloned()
+            .collect();
+        self.vocabulary.extend(added);
+        for (key, positions) in &new_keys {
+            if unchanged.get(key) != Some(positions) {
+                self.unflushed.fetch_add(1, Ordering::SeqCst);
+                self.add_posting(key.clone(), id, positions);
+            }
+        }
+        if flushed {
+            self.mask(id);
+        }
+        for key in old_keys.keys() {
+            if !new_keys.contains_key(key) {
+                self.remove_posting(key.clone(), id);
+                if is_word(key) && self.postings(key).is_empty() {
+                    self.vocabulary.remove(key);
+                }
+            }
+        }
+
+        self.redescribe(id, &doc, metadata, &words);
+        self.blob_store.set(id, doc);
+        self.flush_if_full();
+        Ok(true)
+    }
+
+    /// Update the description of the document with the given id and the year totals for its
+    /// new text `doc`, made up of `words`. The metadata is only replaced if `metadata` is given.
+    fn redescribe(&self, id: usize, doc: &str, metadata: Option<Metadata>, words: &[String]) {
+        let mut documents = self.documents.write().unwrap();
+        let info = &mut documents[id];
+        let mut totals = self.year_totals.lock().unwrap();
+        if let Some(year) = info.metadata.as_ref().and_then(|m| m.year) {
+            if let Some(total) = totals.get_mut(&year) {
+                *total -= info.length;
+            }
+        }
+        if metadata.is_some() {
+            info.metadata = metadata;
+        }
+        if let Some(year) = info.metadata.as_ref().and_then(|m| m.year) {
+            *totals.entry(year).or_default() += words.len();
+        }
+        info.length = words.len();
+        info.hash = dedup::content_hash(doc);
+        info.signature = dedup::signature(words);
+    }
+
+    /// Mask the document with the given id out of every segment
+    fn mask(&self, id: usize) {
+        // Write-locked so that no lookup is between reading the postings in memory and those in
+        // the segments
+        for segment in self.segments.write().unwrap().iter() {
+            segment.masked.write().unwrap().insert(id);
+        }
+    }
+
+    /// Whether the document with the given id has been deleted
+    pub fn is_deleted(&self, id: usize) -> bool {
+        let documents = self.documents.read().unwrap();
+        documents.get(id).is_some_and(|info| info.deleted)
     }
     // TODO:
     // Use the reverse index to get the set of documents that contain the given word.
     pub fn search(&self, word: &str) -> Vec<usize> {
-        let results = self.reverse_index.get(word);
-        results
+        self.postings(&self.key(word))
+            .into_iter()
+            .map(|posting| posting.id)
+            .collect()
+    }
+
+    /// Get the documents that contain `word`, each paired with the number of times it occurs
+    pub fn search_counts(&self, word: &str) -> Vec<(usize, usize)> {
+        self.postings(&self.key(word))
+            .into_iter()
+            .map(|posting| (posting.id, posting.count()))
+            .collect()
+    }
+
+    /// Get every word of the reverse index matching `pattern`, in sorted order. Patterns are
+    /// matched against the words as stored, after tokenization, and never match n-grams.
+    pub fn expand(&self, kind: PatternKind, pattern: &str) -> Result<Vec<String>, regex::Error> {
+        self.vocabulary.expand(kind, pattern)
+    }
+
+    /// Get the words of the reverse index matching `pattern`, in sorted order, along with the set
+    /// of documents that contain any of them, in ascending order
+    pub fn search_pattern(
+        &self,
+        kind: PatternKind,
+        pattern: &str,
+    ) -> Result<(Vec<String>, Vec<usize>), regex::Error> {
+        let terms = self.expand(kind, pattern)?;
+        let mut ids = BTreeSet::new();
+        for term in &terms {
+            ids.extend(self.postings(term).into_iter().map(|posting| posting.id));
+        }
+        Ok((terms, ids.into_iter().collect()))
+    }
+
+    /// Get the words of the reverse index within `max_distance` single character edits of `word`,
+    /// closest first, each paired with the set of documents that contain it
+    pub fn fuzzy_search(&self, word: &str, max_distance: usize) -> Vec<(String, Vec<usize>)> {
+        self.vocabulary
+            .fuzzy(&self.key(word), max_distance)
+            .into_iter()
+            .map(|(term, _)| {
+                let ids = self.postings(&term).into_iter().map(|p| p.id).collect();
+                (term, ids)
+            })
+            .collect()
+    }
+
+    /// Get the `k` documents that best match the words of `query` under `scoring`, each paired
+    /// with its score, from highest to lowest. Documents with equal scores are ordered by id.
+    pub fn ranked_search(&self, query: &str, scoring: Scoring, k: usize) -> Vec<(usize, f64)> {
+        let mut terms = self.tokenizer.tokenize(query);
+        terms.retain(|term| !self.is_stop_word(term));
+        terms.sort();
+        terms.dedup();
+
+        let documents = self.documents.read().unwrap();
+        let count = documents.iter().filter(|doc| !doc.deleted).count() as f64;
+        let total: usize = documents.iter().map(|doc| doc.length).sum();
+        let average = total as f64 / count.max(1.0);
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for term in &terms {
+            let postings = self.postings(term);
+            let df = postings.len() as f64;
+            for posting in postings {
+                // A document is described before its postings are added, and no document can
+                // be added while `documents` is locked, so every posting has a description
+                let info = &documents[posting.id];
+                let tf = posting.count() as f64;
+                let score = match scoring {
+                    Scoring::TfIdf => tf * (1.0 + count / df).ln(),
+                    Scoring::Bm25 { k1, b } => {
+                        let idf = (1.0 + (count - df + 0.5) / (df + 0.5)).ln();
+                        let length = info.length as f64;
+                        let norm = 1.0 - b + b * length / average.max(1.0);
+                        idf * tf * (k1 + 1.0) / (tf + k1 * norm)
+                    }
+                };
+                *scores.entry(posting.id).or_default() += score;
+            }
+        }
+
+        let mut ranked: Vec<(usize, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.truncate(k);
+        ranked
+    }
+
+    /// Get the `k` documents that best match the words of `query`, like `ranked_search`, each
+    /// with the run of up to `words` words that contains the most words of the query
+    pub fn snippets(&self, query: &str, scoring: Scoring, k: usize, words: usize) -> Vec<Snippet> {
+        let terms: HashSet<String> = self
+            .tokenizer
+            .tokenize(query)
+            .into_iter()
+            .filter(|term| !self.is_stop_word(term))
+            .collect();
+        self.ranked_search(query, scoring, k)
+            .into_iter()
+            .map(|(id, score)| {
+                let doc = self.retrieve(id).unwrap_or_default();
+                let (_, body) = split_header(&doc);
+                let (text, highlights) = excerpt(body, &self.tokenizer.tokens(body), &terms, words);
+                Snippet {
+                    id,
+                    score,
+                    text,
+                    highlights,
+                }
+            })
+            .collect()
+    }
+
+    /// Get the set of documents matching a boolean `query`, in ascending order
+    pub fn query(&self, query: &Query) -> Vec<usize> {
+        self.evaluate(query).into_iter().collect()
+    }
+
+    fn evaluate(&self, query: &Query) -> BTreeSet<usize> {
+        match query {
+            Query::Term(term) => self.search(term).into_iter().collect(),
+            Query::Phrase(phrase) => self.phrase(phrase).into_iter().map(|(id, _)| id).collect(),
+            Query::Near {
+                left,
+                right,
+                distance,
+            } => self
+                .near(left, right, *distance)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect(),
+            Query::And(a, b) => {
+                let a = self.evaluate(a);
+                if a.is_empty() {
+                    return a;
+                }
+                a.intersection(&self.evaluate(b)).copied().collect()
+            }
+            Query::Or(a, b) => {
+                let mut a = self.evaluate(a);
+                a.extend(self.evaluate(b));
+                a
+            }
+            Query::Not(q) => {
+                let excluded = self.evaluate(q);
+                (0..self.blob_store.len())
+                    .filter(|&id| self.blob_store.contains(id) && !excluded.contains(&id))
+                    .collect()
+            }
+        }
     }
+
+    /// The reverse index key that `text` is stored under
+    fn key(&self, text: &str) -> String {
+        self.tokenizer.tokenize(text).join(" ")
+    }
+
+    /// Get the postings stored under `key` in the reverse index, in ascending order of document
+    /// id
+    fn postings(&self, key: &str) -> Vec<Posting> {
+        // The segments stay read-locked from before the postings in memory are read until the
+        // segments have been read too, so that a document can't be masked in between
+        let segments = self.segments.read().unwrap();
+        let mut postings: Vec<Posting> = self
+            .reverse_index
+            .get(key)
+            .iter()
+            .flat_map(PostingList::unpack)
+            .collect();
+        for segment in segments.iter() {
+            let masked = segment.masked.read().unwrap();
+            let found = segment.segment.get(key);
+            postings.extend(found.into_iter().filter(|p| !masked.contains(&p.id)));
+        }
+        // A posting may turn up both in memory and in a segment being flushed
+        postings.sort_by_key(|posting| posting.id);
+        postings.dedup_by_key(|posting| posting.id);
+        postings
+    }
+
+    /// Get the set of documents that contain the words of `ngram` contiguously and in order.
+    pub fn search_ngram(&self, ngram: &str) -> Vec<usize> {
+        self.phrase(ngram).into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Get the documents that contain the words of `ngram` contiguously and in order, each paired
+    /// with the number of times the sequence occurs.
+    pub fn count_ngram(&self, ngram: &str) -> Vec<(usize, usize)> {
+        self.phrase(ngram)
+            .into_iter()
+            .map(|(id, offsets)| (id, offsets.len()))
+            .collect()
+    }
+
+    /// Get the documents that contain the words of `phrase` contiguously and in order, each
+    /// paired with the word offsets at which the phrase starts, in ascending order.
+    ///
+    /// Phrases that are stored in the reverse index are looked up directly. The postings of the
+    /// words of longer phrases are lined up by their positions, and if the phrase contains stop
+    /// words, which have no postings, the remaining matches are checked against the stored text.
+    /// A phrase made up only of stop words is searched for in the stored text.
+    pub fn phrase(&self, phrase: &str) -> Vec<(usize, Vec<usize>)> {
+        let words = self.tokenizer.tokenize(phrase);
+        if words.is_empty() {
+            return Vec::new();
+        }
+        let is_indexed = |word: &String| !self.is_stop_word(word);
+        if words.len() <= self.max_ngram && words.iter().all(is_indexed) {
+            return self
+                .postings(&words.join(" "))
+                .into_iter()
+                .map(|posting| (posting.id, posting.positions))
+                .collect();
+        }
+
+        // Each indexed word of the phrase, with how far into the phrase it is
+        let parts: Vec<(usize, &String)> = words
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| is_indexed(word))
+            .collect();
+        if parts.is_empty() {
+            return self.scan(&words);
+        }
+
+        let mut matches: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, &(offset, word)) in parts.iter().enumerate() {
+            let postings = self.postings(word);
+            if i == 0 {
+                for posting in postings {
+                    let starts = posting
+                        .positions
+                        .iter()
+                        .filter_map(|p| p.checked_sub(offset))
+                        .collect();
+                    matches.insert(posting.id, starts);
+                }
+            } else {
+                let postings: HashMap<usize, Vec<usize>> = postings
+                    .into_iter()
+                    .map(|posting| (posting.id, posting.positions))
+                    .collect();
+                matches.retain(|id, starts| match postings.get(id) {
+                    Some(positions) => {
+                        starts.retain(|start| positions.binary_search(&(start + offset)).is_ok());
+                        !starts.is_empty()
+                    }
+                    None => false,
+                });
+            }
+            if matches.is_empty() {
+                break;
+            }
+        }
+        matches.retain(|_, starts| !starts.is_empty());
+        if parts.len() < words.len() {
+            matches.retain(|&id, starts| {
+                let Some(doc) = self.blob_store.get(id) else {
+                    return false;
+                };
+                let (_, body) = split_header(&doc);
+                let doc_words = self.tokenizer.tokenize(body);
+                starts.retain(|&start| doc_words.get(start..start + words.len()) == Some(&words));
+                !starts.is_empty()
+            });
+        }
+
+        let mut matches: Vec<(usize, Vec<usize>)> = matches.into_iter().collect();
+        matches.sort_by_key(|&(id, _)| id);
+        matches
+    }
+
+    /// Find the sequence of `words` by reading through the text of every document
+    fn scan(&self, words: &[String]) -> Vec<(usize, Vec<usize>)> {
+        (0..self.blob_store.len())
+            .filter_map(|id| {
+                let doc = self.blob_store.get(id)?;
+                let (_, body) = split_header(&doc);
+                let doc_words = self.tokenizer.tokenize(body);
+                let starts: Vec<usize> = doc_words
+                    .windows(words.len())
+                    .enumerate()
+                    .filter(|(_, window)| *window == words)
+                    .map(|(start, _)| start)
+                    .collect();
+                (!starts.is_empty()).then_some((id, starts))
+            })
+            .collect()
+    }
+
+    /// Get the documents in which the phrases `left` and `right` occur, in either order, with at
+    /// most `distance` other words between them. Each document is paired with the word offsets
+    /// at which such pairs start, in ascending order.
+    pub fn near(&self, left: &str, right: &str, distance: usize) -> Vec<(usize, Vec<usize>)> {
+        let left_len = self.tokenizer.tokenize(left).len();
+        let right_len = self.tokenizer.tokenize(right).len();
+        let rights: HashMap<usize, Vec<usize>> = self.phrase(right).into_iter().collect();
+        self.phrase(left)
+            .into_iter()
+            .filter_map(|(id, lefts)| {
+                let rights = rights.get(&id)?;
+                let mut starts: Vec<usize> = Vec::new();
+                for &l in &lefts {
+                    for &r in rights {
+                        let within = if l <= r {
+                            r >= l + left_len && r - (l + left_len) <= distance
+                        } else {
+                            l >= r + right_len && l - (r + right_len) <= distance
+                        };
+                        if within {
+                            starts.push(l.min(r));
+                        }
+                    }
+                }
+                starts.sort_unstable();
+                starts.dedup();
+                (!starts.is_empty()).then_some((id, starts))
+            })
+            .collect()
+    }
+
+    /// Get up to `limit` occurrences of `term`, which may be a phrase, each with up to `window`
+    /// words of context on either side. Occurrences are ordered by document and then by offset,
+    /// and line breaks in the context are replaced by spaces.
+    pub fn concordance(&self, term: &str, window: usize, limit: usize) -> Vec<ConcordanceLine> {
+        let length = self.tokenizer.tokenize(term).len();
+        let mut lines = Vec::new();
+        for (id, offsets) in self.phrase(term) {
+            if lines.len() == limit {
+                break;
+            }
+            let Some(doc) = self.retrieve(id) else {
+                continue;
+            };
+            let (_, body) = split_header(&doc);
+            let tokens = self.tokenizer.tokens(body);
+            for offset in offsets.into_iter().take(limit - lines.len()) {
+                let end = offset + length - 1;
+                if end >= tokens.len() {
+                    continue;
+                }
+                let first = offset.saturating_sub(window);
+                let last = (end + window).min(tokens.len() - 1);
+                let text = |start: usize, stop: usize| {
+                    body[start..stop]
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+                lines.push(ConcordanceLine {
+                    id,
+                    offset,
+                    left: text(tokens[first].span.start, tokens[offset].span.start),
+                    keyword: text(tokens[offset].span.start, tokens[end].span.end),
+                    right: text(tokens[end].span.end, tokens[last].span.end),
+                });
+            }
+        }
+        lines
+    }
+
+    /// Get the `k` words most strongly associated with `term`, which may be a phrase, by
+    /// `measure`. Only words occurring at least `min_count` times within `window` words of an
+    /// occurrence of `term` are considered, and stop words are never considered.
+    ///
+    /// Each word position of the corpus is classed by whether it is within the window of an
+    /// occurrence of `term` and whether it holds the candidate word, and both measures compare
+    /// the resulting 2x2 table of counts with what would be expected if the two were unrelated.
+    pub fn collocations(
+        &self,
+        term: &str,
+        window: usize,
+        measure: Association,
+        min_count: usize,
+        k: usize,
+    ) -> Vec<Collocation> {
+        let length = self.tokenizer.tokenize(term).len();
+        // The number of window positions, and the occurrences of each word within them
+        let mut positions = 0;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for (id, offsets) in self.phrase(term) {
+            let Some(doc) = self.retrieve(id) else {
+                continue;
+            };
+            let (_, body) = split_header(&doc);
+            let words = self.tokenizer.tokenize(body);
+            let mut near = BTreeSet::new();
+            for &offset in &offsets {
+                let end = (offset + length + window).min(words.len());
+                near.extend(offset.saturating_sub(window)..end);
+            }
+            for offset in offsets {
+                for i in offset..offset + length {
+                    near.remove(&i);
+                }
+            }
+            positions += near.len();
+            for i in near {
+                if !self.is_stop_word(&words[i]) {
+                    *counts.entry(words[i].clone()).or_default() += 1;
+                }
+            }
+        }
+
+        let total: usize = {
+            let documents = self.documents.read().unwrap();
+            documents.iter().map(|doc| doc.length).sum()
+        };
+        let mut collocations: Vec<Collocation> = counts
+            .into_iter()
+            .filter(|&(_, count)| count >= min_count.max(1))
+            .map(|(word, count)| {
+                let occurrences: usize = self.postings(&word).iter().map(Posting::count).sum();
+                let (pmi, log_likelihood) = association(count, positions, occurrences, total);
+                Collocation {
+                    word,
+                    count,
+                    pmi,
+                    log_likelihood,
+                }
+            })
+            .collect();
+        collocations.sort_by(|a, b| {
+            let (a_score, b_score) = match measure {
+                Association::Pmi => (a.pmi, b.pmi),
+                Association::LogLikelihood => (a.log_likelihood, b.log_likelihood),
+            };
+            b_score
+                .total_cmp(&a_score)
+                .then_with(|| a.word.cmp(&b.word))
+        });
+        collocations.truncate(k);
+        collocations
+    }
+
+    /// Get the frequency of `ngram` for every publication year in the archive, in chronological
+    /// order. Documents without a known year are left out.
+    ///
+    /// With `options.normalize`, the frequency for a year is the number of occurrences of `ngram`
+    /// in the documents published that year divided by the number of words in those documents,
+    /// and otherwise it is the raw number of occurrences. With a `options.smoothing` of `s`, each
+    /// year's value is then replaced by the average of the values of the years in the archive at
+    /// most `s` years before or after it.
+    pub fn timeline(&self, ngram: &str, options: TimelineOptions) -> Vec<(u32, f64)> {
+        let counts = self.count_ngram(ngram);
+        let mut years: BTreeMap<u32, usize> = self
+            .year_totals
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|&year| (year, 0))
+            .collect();
+        {
+            let documents = self.documents.read().unwrap();
+            for (id, count) in counts {
+                let info = documents.get(id);
+                if let Some(year) = info.and_then(|i| i.metadata.as_ref()).and_then(|m| m.year) {
+                    *years.entry(year).or_default() += count;
+                }
+            }
+        }
+
+        let totals = self.year_totals.lock().unwrap();
+        let points: Vec<(u32, f64)> = years
+            .into_iter()
+            .map(|(year, count)| {
+                let total = totals.get(&year).copied().unwrap_or(0);
+                let value = if !options.normalize {
+                    count as f64
+                } else if total == 0 {
+                    0.0
+                } else {
+                    count as f64 / total as f64
+                };
+                (year, value)
+            })
+            .collect();
+        smooth(&points, options.smoothing)
+    }
+
+    /// Get the total number of words published in each publication year, in chronological order
+    pub fn year_totals(&self) -> Vec<(u32, usize)> {
+        let totals = self.year_totals.lock().unwrap();
+        totals.iter().map(|(&year, &total)| (year, total)).collect()
+    }
+
+    /// Get the size of the archive along with its `top_k` most frequent indexed words
+    pub fn stats(&self, top_k: usize) -> CorpusStats {
+        let (documents, tokens) = {
+            let documents = self.documents.read().unwrap();
+            let tokens = documents.iter().map(|doc| doc.length).sum();
+            (documents.iter().filter(|doc| !doc.deleted).count(), tokens)
+        };
+        let words = self.vocabulary.terms();
+        let counts = words.iter().map(|word| {
+            let count = self.postings(word).iter().map(Posting::count).sum();
+            (word.clone(), count)
+        });
+        CorpusStats {
+            documents,
+            tokens,
+            vocabulary: words.len(),
+            average_length: tokens as f64 / documents.max(1) as f64,
+            top_terms: most_frequent(counts, top_k),
+        }
+    }
+
+    /// Get the indexed words starting with `prefix` with their frequencies, in sorted order,
+    /// skipping the first `offset` and returning at most `limit`, along with the total number of
+    /// words starting with `prefix`. The page is read off the vocabulary, so only the postings
+    /// of the words returned are looked up.
+    pub fn list_terms(
+        &self,
+        prefix: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<TermFrequency>, usize) {
+        let (terms, total) = self.vocabulary.page(prefix, offset, limit);
+        let page = terms
+            .into_iter()
+            .map(|term| {
+                let postings = self.postings(&term);
+                TermFrequency {
+                    documents: postings.len(),
+                    occurrences: postings.iter().map(Posting::count).sum(),
+                    term,
+                }
+            })
+            .collect();
+        (page, total)
+    }
+
+    /// Get the size of the document with the given id along with its `top_k` most frequent
+    /// indexed words, or None if the id is invalid
+    pub fn doc_stats(&self, id: usize, top_k: usize) -> Option<DocStats> {
+        let doc = self.retrieve(id)?;
+        let (_, body) = split_header(&doc);
+        let words = self.tokenizer.tokenize(body);
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for word in &words {
+            if !self.is_stop_word(word) {
+                *counts.entry(word.clone()).or_default() += 1;
+            }
+        }
+        Some(DocStats {
+            id,
+            tokens: words.len(),
+            distinct: counts.len(),
+            top_terms: most_frequent(counts, top_k),
+        })
+    }
+
     // TODO:
     // Retrieve the document with the given id from the blob store.
     // Return None if the given id is invalid.
+    //
+    // Deleted documents are also returned as None; use `is_deleted` to tell them apart.
     pub fn retrieve(&self, id: usize) -> Option<String> {
-        let store = self.blob_store.lock().unwrap();
-        store.get(id).cloned()
-        
+        self.blob_store.get(id)
+    }
+
+    /// Get the metadata of the document with the given id, if it has any
+    pub fn metadata(&self, id: usize) -> Option<Metadata> {
+        let documents = self.documents.read().unwrap();
+        documents.get(id)?.metadata.clone()
+    }
+}
+
+/// Get the `k` words with the highest counts, most frequent first and then in sorted order
+fn most_frequent<I: IntoIterator<Item = (String, usize)>>(
+    counts: I,
+    k: usize,
+) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(k);
+    counts
+}
+
+/// Get the PMI and log-likelihood of a word occurring `count` times in `window` positions near
+/// another word, given that it occurs `occurrences` times among all `total` positions
+fn association(count: usize, window: usize, occurrences: usize, total: usize) -> (f64, f64) {
+    let n = total as f64;
+    let observed = [
+        [count, window.saturating_sub(count)],
+        [
+            occurrences.saturating_sub(count),
+            total.saturating_sub(window + occurrences.saturating_sub(count)),
+        ],
+    ];
+    let rows = [window as f64, n - window as f64];
+    let columns = [occurrences as f64, n - occurrences as f64];
+    let mut log_likelihood = 0.0;
+    for (i, row) in observed.iter().enumerate() {
+        for (j, &o) in row.iter().enumerate() {
+            let expected = rows[i] * columns[j] / n;
+            if o > 0 && expected > 0.0 {
+                log_likelihood += o as f64 * (o as f64 / expected).ln();
+            }
+        }
+    }
+    let pmi = (count as f64 * n / (window as f64 * occurrences as f64)).log2();
+    (pmi, 2.0 * log_likelihood)
+}
+
+/// Find the run of up to `words` tokens of `text` containing the most tokens whose term is in
+/// `terms`, preferring the earliest. Return the run with whitespace collapsed to single spaces,
+/// along with the byte ranges of the matching tokens within it.
+fn excerpt(
+    text: &str,
+    tokens: &[Token],
+    terms: &HashSet<String>,
+    words: usize,
+) -> (String, Vec<Range<usize>>) {
+    let words = words.max(1).min(tokens.len());
+    if words == 0 {
+        return (String::new(), Vec::new());
+    }
+    let matched: Vec<bool> = tokens.iter().map(|t| terms.contains(&t.term)).collect();
+    let mut count = matched[..words].iter().filter(|&&m| m).count();
+    let (mut best, mut best_count) = (0, count);
+    for start in 1..=tokens.len() - words {
+        count = count + matched[start + words - 1] as usize - matched[start - 1] as usize;
+        if count > best_count {
+            best = start;
+            best_count = count;
+        }
+    }
+
+    let mut excerpt = String::new();
+    let mut highlights = Vec::new();
+    for i in best..best + words {
+        if i > best {
+            let gap = &text[tokens[i - 1].span.end..tokens[i].span.start];
+            let mut space = false;
+            for c in gap.chars() {
+                if !c.is_whitespace() {
+                    excerpt.push(c);
+                    space = false;
+                } else if !space {
+                    excerpt.push(' ');
+                    space = true;
+                }
+            }
+        }
+        let start = excerpt.len();
+        excerpt.push_str(&text[tokens[i].span.clone()]);
+        if matched[i] {
+            highlights.push(start..excerpt.len());
+        }
+    }
+    (excerpt, highlights)
+}
+
+/// Replace every value with the average of the values whose year is at most `window` years away.
+/// `points` must be sorted by year.
+fn smooth(points: &[(u32, f64)], window: u32) -> Vec<(u32, f64)> {
+    if window == 0 {
+        return points.to_vec();
+    }
+    points
+        .iter()
+        .map(|&(year, _)| {
+            let neighbours: Vec<f64> = points
+                .iter()
+                .filter(|(other, _)| year.abs_diff(*other) <= window)
+                .map(|&(_, value)| value)
+                .collect();
+            let average = neighbours.iter().sum::<f64>() / neighbours.len() as f64;
+            (year, average)
+        })
+        .collect()
+}
+
+/// Split a document into the metadata in its `[Title by Author Year]` header line, if it has one,
+/// and the text that follows the header. The author and year may be missing from the header.
+fn split_header(doc: &str) -> (Option<Metadata>, &str) {
+    let (line, rest) = doc.split_once('\n').unwrap_or((doc, ""));
+    let header = match line
+        .trim()
+        .strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+    {
+        Some(header) => header.trim(),
+        None => return (None, doc),
+    };
+
+    let (rest_of_header, year) = match header.rsplit_once(' ') {
+        Some((front, last)) if !last.is_empty() && last.chars().all(|c| c.is_ascii_digit()) => {
+            (front, last.parse().ok())
+        }
+        _ => (header, None),
+    };
+    let (title, author) = rest_of_header
+        .rsplit_once(" by ")
+        .unwrap_or((rest_of_header, ""));
+    let metadata = Metadata {
+        title: title.trim().trim_end_matches(',').to_string(),
+        author: author.trim().to_string(),
+        year,
+    };
+    (Some(metadata), rest)
+}
+
+/// Whether a key of the reverse index is a single word rather than an n-gram, whose words are
+/// joined with a space
+fn is_word(key: &str) -> bool {
+    !key.contains(' ')
+}
+
+/// Fail with an error if a change read back from storage couldn't be redone
+fn check_applied(applied: bool) -> io::Result<()> {
+    if applied {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the log does not match the documents stored before it",
+        ))
     }
 }
@@ -0,0 +1,61 @@
+// Exact duplicates are found by hashing the full text of each document. Near duplicates, like two
+// editions of a book that differ in a few corrected words, are found with MinHash: each document
+// is reduced to the set of its overlapping runs of `SHINGLE` words, and the fraction of positions
+// at which the signatures of two documents agree estimates the Jaccard similarity of those sets.
+
+/// The number of words in a shingle
+pub const SHINGLE: usize = 3;
+
+/// The number of hash functions, and so the length of a signature
+pub const PERMUTATIONS: usize = 64;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The 64-bit FNV-1a hash of `text`
+pub fn content_hash(text: &str) -> u64 {
+    hash_bytes(text.as_bytes())
+}
+
+/// The 64-bit FNV-1a hash of `bytes`
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// The MinHash signature of the shingles of `words`. A text shorter than a shingle is treated as
+/// a single shingle, and an empty text has an empty signature.
+pub fn signature(words: &[String]) -> Vec<u64> {
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let mut signature = vec![u64::MAX; PERMUTATIONS];
+    for shingle in words.windows(SHINGLE.min(words.len())) {
+        let hash = content_hash(&shingle.join(" "));
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(mix(hash ^ (i as u64).wrapping_mul(FNV_PRIME)));
+        }
+    }
+    signature
+}
+
+/// The estimated fraction of shingles shared by the texts with signatures `a` and `b`
+pub fn similarity(a: &[u64], b: &[u64]) -> f64 {
+    if a.is_empty() || a.len() != b.len() {
+        return 0.0;
+    }
+    let equal = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    equal as f64 / a.len() as f64
+}
+
+/// The SplitMix64 finalizer, which turns one hash into an unrelated one
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
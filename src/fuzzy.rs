@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+// A BK-tree stores words so that all words within some edit distance of a query can be found
+// without comparing the query to every word. Each child of a node is labelled with its distance
+// to that node, and by the triangle inequality a word within `max` of the query can only be under
+// children labelled within `max` of the query's distance to the node.
+
+/// The Levenshtein distance between `a` and `b`: the fewest single character insertions,
+/// deletions and substitutions that turn one into the other
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // `row[j]` is the distance between the prefix of `a` seen so far and the first `j` of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+struct Node {
+    term: String,
+    /// Whether `term` has been removed. The node stays in the tree to keep its children
+    /// reachable.
+    removed: bool,
+    /// The index of the child at each distance from `term`
+    children: HashMap<usize, usize>,
+}
+
+/// A set of terms that can be searched by edit distance
+#[derive(Default)]
+pub struct BkTree {
+    /// Every node of the tree, with the root first
+    nodes: Vec<Node>,
+}
+
+impl BkTree {
+    /// Create a new empty tree
+    pub fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    /// Add `term` to the tree, unless it is already there
+    pub fn insert(&mut self, term: String) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                term,
+                removed: false,
+                children: HashMap::new(),
+            });
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = levenshtein(&self.nodes[current].term, &term);
+            if distance == 0 {
+                self.nodes[current].removed = false;
+                return;
+            }
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(Node {
+                        term,
+                        removed: false,
+                        children: HashMap::new(),
+                    });
+                    self.nodes[current].children.insert(distance, index);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Remove `term` from the tree, if it is there
+    pub fn remove(&mut self, term: &str) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = levenshtein(&self.nodes[current].term, term);
+            if distance == 0 {
+                self.nodes[current].removed = true;
+                return;
+            }
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => return,
+            }
+        }
+    }
+
+    /// Get every term within `max_distance` edits of `query`, paired with its distance, closest
+    /// first and then in sorted order
+    pub fn search(&self, query: &str, max_distance: usize) -> Vec<(String, usize)> {
+        let mut matches = Vec::new();
+        if self.nodes.is_empty() {
+            return matches;
+        }
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            let node = &self.nodes[index];
+            let distance = levenshtein(&node.term, query);
+            if distance <= max_distance && !node.removed {
+                matches.push((node.term.clone(), distance));
+            }
+            let low = distance.saturating_sub(max_distance);
+            let high = distance + max_distance;
+            for (&edge, &child) in &node.children {
+                if (low..=high).contains(&edge) {
+                    pending.push(child);
+                }
+            }
+        }
+        matches.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        matches
+    }
+}
@@ -1,6 +1,16 @@
+pub mod blob;
 pub mod client;
 pub mod database;
+pub mod dedup;
+pub mod fuzzy;
+pub mod lz;
 pub mod message;
 pub mod multimap;
 pub mod pool;
+pub mod query;
+pub mod segment;
 pub mod server;
+pub mod storage;
+pub mod tokenizer;
+pub mod varint;
+pub mod vocabulary;
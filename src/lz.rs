@@ -0,0 +1,125 @@
+// A small LZ77 codec in the style of LZ4. The compressed data is a series of sequences, each a
+// run of literal bytes followed by a match: a copy of earlier output, given by how far back it
+// starts and how long it is. A sequence starts with a token byte whose high four bits are the
+// number of literals and whose low four bits are the length of the match minus `MIN_MATCH`.
+// Either count is extended by further bytes when its four bits are all set, each adding up to
+// 255. The last sequence has only literals.
+//
+//     token, [literal length], literals, offset (2 bytes), [match length]
+//
+// Matches are found by hashing every run of `MIN_MATCH` bytes and looking up where the same hash
+// last occurred, which finds far from every match but is fast, and plenty for running text.
+
+/// The length of the shortest match worth encoding
+const MIN_MATCH: usize = 4;
+
+/// The furthest back a match can start
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+const HASH_BITS: u32 = 14;
+
+/// Compress `input`
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    // The last position at which each hash of `MIN_MATCH` bytes occurred, plus one
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let mut literals = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= input.len() {
+        let hash = hash(&input[i..i + MIN_MATCH]);
+        let candidate = table[hash].checked_sub(1);
+        table[hash] = i + 1;
+        let Some(start) = candidate.filter(|&start| {
+            i - start <= MAX_OFFSET && input[start..start + MIN_MATCH] == input[i..i + MIN_MATCH]
+        }) else {
+            i += 1;
+            continue;
+        };
+        let mut len = MIN_MATCH;
+        while i + len < input.len() && input[start + len] == input[i + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &input[literals..i], Some((i - start, len)));
+        i += len;
+        literals = i;
+    }
+    write_sequence(&mut out, &input[literals..], None);
+    out
+}
+
+/// Decompress data written by `compress`, or return `None` if it is corrupt
+pub fn decompress(input: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 3);
+    let mut i = 0;
+    loop {
+        // The data must end with a sequence of only literals, so running out early means it
+        // was cut short
+        let token = *input.get(i)?;
+        i += 1;
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += read_length(input, &mut i)?;
+        }
+        out.extend_from_slice(input.get(i..i.checked_add(literals)?)?);
+        i += literals;
+        if i == input.len() {
+            return Some(out);
+        }
+
+        let offset = u16::from_le_bytes(input.get(i..i + 2)?.try_into().ok()?) as usize;
+        i += 2;
+        let mut len = (token & 15) as usize;
+        if len == 15 {
+            len += read_length(input, &mut i)?;
+        }
+        len += MIN_MATCH;
+        if offset == 0 || offset > out.len() {
+            return None;
+        }
+        // The match may overlap the bytes it produces, so it is copied one byte at a time
+        let start = out.len() - offset;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let extra = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) << 4) | extra.min(15)) as u8);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if extra >= 15 {
+            write_length(out, extra - 15);
+        }
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 255 {
+        out.push(255);
+        n -= 255;
+    }
+    out.push(n as u8);
+}
+
+fn read_length(input: &[u8], i: &mut usize) -> Option<usize> {
+    let mut n = 0usize;
+    loop {
+        let byte = *input.get(*i)?;
+        *i += 1;
+        n = n.checked_add(byte as usize)?;
+        if byte != 255 {
+            return Some(n);
+        }
+    }
+}
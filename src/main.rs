@@ -1,6 +1,16 @@
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use ngram::client::Client;
+use ngram::database::{
+    Association, Collocation, ConcordanceLine, CorpusStats, Database, DocStats, DuplicatePolicy,
+    Scoring, Snippet, TermFrequency, DEFAULT_FLUSH_THRESHOLD, DEFAULT_MAX_NGRAM,
+    DEFAULT_NEAR_DUPLICATE_THRESHOLD,
+};
+use ngram::message::Response;
 use ngram::server::Server;
+use ngram::tokenizer::{default_stop_words, parse_stop_words, Pipeline, WhitespaceTokenizer};
+use ngram::vocabulary::PatternKind;
+use std::time::Duration;
 
 // TODO:
 // Fill out the `Args` struct to parse the command line arguments. You may find clap "subcommands"
@@ -16,6 +26,44 @@ struct Args {
 enum Commands {
     Server {
         listen_port: u16,
+
+        /// Index every sequence of up to this many words
+        #[arg(long, default_value_t = DEFAULT_MAX_NGRAM)]
+        max_ngram: usize,
+
+        /// How to split documents and queries into words
+        #[arg(long, value_enum, default_value_t = TokenizerKind::Whitespace)]
+        tokenizer: TokenizerKind,
+
+        /// Leave out numbers like "1816"; only allowed with the unicode tokenizer
+        #[arg(long)]
+        remove_numerals: bool,
+
+        /// Leave common words out of the index, using the whitespace separated list in PATH or
+        /// the built-in list from data/words.txt if no PATH is given
+        #[arg(long, value_name = "PATH", num_args = 0..=1)]
+        stop_words: Option<Option<String>>,
+
+        /// What to do when a client publishes a document that is already published
+        #[arg(long, value_enum, default_value_t = DuplicateArg::Allow)]
+        duplicates: DuplicateArg,
+
+        /// Report published documents sharing at least this fraction of their text as near
+        /// duplicates
+        #[arg(long, default_value_t = DEFAULT_NEAR_DUPLICATE_THRESHOLD)]
+        near_duplicate_threshold: f64,
+
+        /// Keep the archive in this directory, so that it survives restarting the server
+        #[arg(long, value_name = "DIR")]
+        storage: Option<String>,
+
+        /// Flush the index to a new segment in storage once this many postings are held in memory
+        #[arg(long, value_name = "POSTINGS", default_value_t = DEFAULT_FLUSH_THRESHOLD)]
+        flush_threshold: usize,
+
+        /// Write a snapshot of the archive to its storage every this many seconds
+        #[arg(long, value_name = "SECONDS", requires = "storage")]
+        snapshot_interval: Option<u64>,
     },
     Client {
         server_address: String,
@@ -25,11 +73,222 @@ enum Commands {
         action: ClientCommands,
     },
 }
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TokenizerKind {
+    /// Split on whitespace and keep words exactly as written
+    Whitespace,
+    /// Split at Unicode word boundaries, lowercase words and strip punctuation
+    Unicode,
+}
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DuplicateArg {
+    /// Publish it again under a new id
+    Allow,
+    /// Return the id of the existing copy without publishing it again
+    ReturnExisting,
+    /// Refuse to publish it
+    Reject,
+}
 #[derive(Subcommand, Debug)]
 enum ClientCommands {
-    Publish { document: String },
-    Search { word: String },
-    Retrieve { id: usize },
+    Publish {
+        document: String,
+
+        /// Title to publish the document under instead of the one in its header
+        #[arg(long, requires_all = ["author", "year"])]
+        title: Option<String>,
+
+        /// Author to publish the document under instead of the one in its header
+        #[arg(long, requires_all = ["title", "year"])]
+        author: Option<String>,
+
+        /// Publication year to publish the document under instead of the one in its header
+        #[arg(long, requires_all = ["title", "author"])]
+        year: Option<u32>,
+    },
+    Search {
+        word: String,
+
+        /// Have the server send the results compressed
+        #[arg(long)]
+        compressed: bool,
+    },
+    Retrieve {
+        id: usize,
+    },
+    /// Delete a document so that it no longer turns up in searches
+    Delete {
+        id: usize,
+    },
+    /// Replace the text of a document with the contents of a file, keeping its id
+    Replace {
+        id: usize,
+        document: String,
+    },
+    /// Write a snapshot of the archive to the server's storage
+    Snapshot,
+    /// Search for documents containing a sequence of words, e.g. "the whale"
+    Ngram {
+        ngram: String,
+    },
+    /// Count how often a word or sequence of words occurs in each document
+    Count {
+        ngram: String,
+    },
+    /// Show the frequency of a word or sequence of words in each publication year
+    Timeline {
+        ngram: String,
+
+        /// Show raw occurrence counts instead of dividing by the words published each year
+        #[arg(long)]
+        raw: bool,
+
+        /// Average each year with the years up to this many years before and after it
+        #[arg(long, default_value_t = 0)]
+        smoothing: u32,
+    },
+    /// Search with a boolean query like "whale AND (ship OR boat) NOT ahab"
+    Query {
+        query: String,
+    },
+    /// Search for an exact phrase, showing where in each document it starts
+    Phrase {
+        phrase: String,
+    },
+    /// Search for two words or phrases close to each other
+    Near {
+        left: String,
+        right: String,
+
+        /// The most words allowed between the two
+        #[arg(long, default_value_t = 5)]
+        distance: usize,
+    },
+    /// Search for every word matching a pattern, showing which words matched
+    Pattern {
+        pattern: String,
+
+        /// How to interpret the pattern
+        #[arg(long, value_enum, default_value_t = PatternArg::Glob)]
+        kind: PatternArg,
+    },
+    /// Search for every spelling of a word within a number of typos, like "shew" for "show"
+    Fuzzy {
+        word: String,
+
+        /// The most single character insertions, deletions or substitutions allowed
+        #[arg(long, default_value_t = 1)]
+        max_distance: usize,
+    },
+    /// Show the documents that best match some words, with their relevance scores
+    Rank {
+        query: String,
+
+        /// The number of documents to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        /// How to score each document
+        #[arg(long, value_enum, default_value_t = ScoringArg::Bm25)]
+        scoring: ScoringArg,
+
+        /// How quickly repeated occurrences of a word stop adding to the BM25 score
+        #[arg(long, default_value_t = 1.2)]
+        k1: f64,
+
+        /// How strongly BM25 scores are normalized by document length, from 0 to 1
+        #[arg(long, default_value_t = 0.75)]
+        b: f64,
+
+        /// Show an excerpt of up to this many words from each document, with the words of the
+        /// query in [brackets]
+        #[arg(long, value_name = "WORDS")]
+        snippets: Option<usize>,
+    },
+    /// Show every occurrence of a word or phrase in the middle of the words around it
+    Concordance {
+        term: String,
+
+        /// The number of words to show on each side
+        #[arg(long, default_value_t = 5)]
+        window: usize,
+
+        /// The most occurrences to show
+        #[arg(long, default_value_t = 25)]
+        limit: usize,
+    },
+    /// Show the words that most strongly tend to occur near a word or phrase
+    Collocations {
+        term: String,
+
+        /// How many words on each side count as near
+        #[arg(long, default_value_t = 5)]
+        window: usize,
+
+        /// How to measure the strength of association
+        #[arg(long, value_enum, default_value_t = MeasureArg::LogLikelihood)]
+        measure: MeasureArg,
+
+        /// Leave out words occurring near the term fewer than this many times
+        #[arg(long, default_value_t = 3)]
+        min_count: usize,
+
+        /// The number of words to show
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
+    /// Show the size of the archive, or of one document, and its most frequent words
+    Stats {
+        /// Describe the document with this id instead of the whole archive
+        #[arg(long)]
+        id: Option<usize>,
+
+        /// The number of most frequent words to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// List the indexed words with the number of documents containing them and their total
+    /// number of occurrences
+    Terms {
+        /// Only list words starting with this
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// Skip this many words
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// The most words to list
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+
+        /// Print comma separated values instead of a table
+        #[arg(long)]
+        csv: bool,
+    },
+}
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PatternArg {
+    /// Words starting with the pattern
+    Prefix,
+    /// Words matching the pattern, where * matches any characters and ? matches one
+    Glob,
+    /// Words matched in full by the regular expression
+    Regex,
+}
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ScoringArg {
+    /// Term frequency weighted by inverse document frequency
+    TfIdf,
+    /// Okapi BM25
+    Bm25,
+}
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MeasureArg {
+    /// Pointwise mutual information, which favours rare words
+    Pmi,
+    /// Dunning's log-likelihood ratio
+    LogLikelihood,
 }
 
 // TODO:
@@ -39,8 +298,67 @@ enum ClientCommands {
 fn main() {
     let args = Args::parse();
     match args.command {
-        Commands::Server { listen_port } => {
-            let server = Server::new();
+        Commands::Server {
+            listen_port,
+            max_ngram,
+            tokenizer,
+            remove_numerals,
+            stop_words,
+            duplicates,
+            near_duplicate_threshold,
+            storage,
+            flush_threshold,
+            snapshot_interval,
+        } => {
+            let policy = match duplicates {
+                DuplicateArg::Allow => DuplicatePolicy::Allow,
+                DuplicateArg::ReturnExisting => DuplicatePolicy::ReturnExisting,
+                DuplicateArg::Reject => DuplicatePolicy::Reject,
+            };
+            let database = Database::new()
+                .with_max_ngram(max_ngram)
+                .with_duplicate_policy(policy)
+                .with_near_duplicate_threshold(near_duplicate_threshold)
+                .with_flush_threshold(flush_threshold);
+            let database = match tokenizer {
+                TokenizerKind::Whitespace if remove_numerals => Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--remove-numerals only applies to --tokenizer unicode",
+                    )
+                    .exit(),
+                TokenizerKind::Whitespace => database.with_tokenizer(WhitespaceTokenizer),
+                TokenizerKind::Unicode => database.with_tokenizer(Pipeline {
+                    remove_numerals,
+                    ..Pipeline::default()
+                }),
+            };
+            let database = match stop_words {
+                Some(Some(path)) => match std::fs::read_to_string(&path) {
+                    Ok(text) => database.with_stop_words(parse_stop_words(&text)),
+                    Err(e) => {
+                        eprintln!("Failed to read stop words from {}: {}", path, e);
+                        return;
+                    }
+                },
+                Some(None) => database.with_stop_words(default_stop_words()),
+                None => database,
+            };
+            let database = match storage {
+                Some(dir) => match database.with_storage(&dir) {
+                    Ok(database) => database,
+                    Err(e) => {
+                        eprintln!("Failed to open storage in {}: {}", dir, e);
+                        return;
+                    }
+                },
+                None => database,
+            };
+            let server = Server::with_database(database);
+            let server = match snapshot_interval {
+                Some(seconds) => server.with_snapshot_interval(Duration::from_secs(seconds)),
+                None => server,
+            };
             server.run(listen_port);
         }
         Commands::Client {
@@ -49,21 +367,275 @@ fn main() {
             action,
         } => {
             let client = Client::new(&server_address, server_port);
-            match action {
-                ClientCommands::Publish { document } => {
-                    let _response = client.publish_from_path(&document);
-                    
-                    
+            let csv = matches!(action, ClientCommands::Terms { csv: true, .. });
+            let response = match action {
+                ClientCommands::Publish {
+                    document,
+                    title: Some(title),
+                    author: Some(author),
+                    year: Some(year),
+                } => client.publish_from_path_with_metadata(&document, &title, &author, year),
+                ClientCommands::Publish { document, .. } => client.publish_from_path(&document),
+                ClientCommands::Search {
+                    word,
+                    compressed: false,
+                } => client.search(&word),
+                ClientCommands::Search {
+                    word,
+                    compressed: true,
+                } => client.search_compressed(&word),
+                ClientCommands::Retrieve { id } => client.retrieve(id),
+                ClientCommands::Delete { id } => client.delete(id),
+                ClientCommands::Replace { id, document } => client.replace_from_path(id, &document),
+                ClientCommands::Snapshot => client.snapshot(),
+                ClientCommands::Ngram { ngram } => client.search_ngram(&ngram),
+                ClientCommands::Count { ngram } => client.count(&ngram),
+                ClientCommands::Timeline {
+                    ngram,
+                    raw,
+                    smoothing,
+                } => client.timeline(&ngram, !raw, smoothing),
+                ClientCommands::Query { query } => client.query(&query),
+                ClientCommands::Phrase { phrase } => client.phrase(&phrase),
+                ClientCommands::Near {
+                    left,
+                    right,
+                    distance,
+                } => client.near(&left, &right, distance),
+                ClientCommands::Pattern { pattern, kind } => {
+                    let kind = match kind {
+                        PatternArg::Prefix => PatternKind::Prefix,
+                        PatternArg::Glob => PatternKind::Glob,
+                        PatternArg::Regex => PatternKind::Regex,
+                    };
+                    client.search_pattern(&pattern, kind)
+                }
+                ClientCommands::Fuzzy { word, max_distance } => {
+                    client.fuzzy_search(&word, max_distance)
+                }
+                ClientCommands::Rank {
+                    query,
+                    top,
+                    scoring,
+                    k1,
+                    b,
+                    snippets,
+                } => {
+                    let scoring = match scoring {
+                        ScoringArg::TfIdf => Scoring::TfIdf,
+                        ScoringArg::Bm25 => Scoring::Bm25 { k1, b },
+                    };
+                    match snippets {
+                        Some(words) => client.snippets(&query, scoring, top, words),
+                        None => client.ranked_search(&query, scoring, top),
+                    }
+                }
+                ClientCommands::Concordance {
+                    term,
+                    window,
+                    limit,
+                } => client.concordance(&term, window, limit),
+                ClientCommands::Collocations {
+                    term,
+                    window,
+                    measure,
+                    min_count,
+                    top,
+                } => {
+                    let measure = match measure {
+                        MeasureArg::Pmi => Association::Pmi,
+                        MeasureArg::LogLikelihood => Association::LogLikelihood,
+                    };
+                    client.collocations(&term, window, measure, min_count, top)
+                }
+                ClientCommands::Stats { id: Some(id), top } => client.doc_stats(id, top),
+                ClientCommands::Stats { id: None, top } => client.stats(top),
+                ClientCommands::Terms {
+                    prefix,
+                    offset,
+                    limit,
+                    ..
+                } => client.list_terms(&prefix, offset, limit),
+            };
+            match response {
+                Some(Response::PublishSuccess(id, similar)) => {
+                    println!("Published as document {}", id);
+                    for (other, similarity) in similar {
+                        println!(
+                            "Nearly duplicates document {} ({:.0}% similar)",
+                            other,
+                            similarity * 100.0
+                        );
+                    }
+                }
+                Some(Response::Duplicate(id)) => {
+                    eprintln!("Already published as document {}", id)
+                }
+                Some(Response::CountSuccess(mut counts)) => print_counts(&mut counts),
+                Some(Response::TimelineSuccess(points)) => print_timeline(&points),
+                Some(Response::PositionsSuccess(matches)) => print_positions(&matches),
+                Some(Response::PatternSuccess(terms, ids)) => {
+                    println!("Matched words: {}", terms.join(", "));
+                    println!("Documents: {:?}", ids);
                 }
-                ClientCommands::Search { word } => {
-                    let _response = client.search(&word);
-                    
+                Some(Response::FuzzySuccess(variants)) => {
+                    for (term, ids) in variants {
+                        println!("{}: {:?}", term, ids);
+                    }
                 }
-                ClientCommands::Retrieve { id } => {
-                    let _response = client.retrieve(id);
-                    
+                Some(Response::RankedSuccess(scores)) => print_scores(&scores),
+                Some(Response::ConcordanceSuccess(lines)) => print_concordance(&lines),
+                Some(Response::SnippetSuccess(snippets)) => print_snippets(&snippets),
+                Some(Response::CollocationSuccess(collocations)) => {
+                    print_collocations(&collocations)
                 }
+                Some(Response::StatsSuccess(stats)) => print_stats(&stats),
+                Some(Response::DocStatsSuccess(stats)) => print_doc_stats(&stats),
+                Some(Response::TermsSuccess(terms, _)) if csv => print_terms_csv(&terms),
+                Some(Response::TermsSuccess(terms, total)) => print_terms(&terms, total),
+                Some(Response::Deleted(id)) => eprintln!("Document {} was deleted", id),
+                Some(Response::SnapshotSuccess(generation)) => {
+                    println!("Wrote snapshot {}", generation)
+                }
+                Some(Response::CompressedSearchSuccess(ids)) => println!("{:?}", ids),
+                Some(Response::Error(reason)) => eprintln!("Error: {}", reason),
+                Some(response) => println!("{:?}", response),
+                None => eprintln!("No valid response from the server"),
             }
         }
     }
 }
+
+/// Print per-document occurrence counts as a table, most frequent first
+fn print_counts(counts: &mut [(usize, usize)]) {
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    println!("{:>10}  {:>10}", "document", "count");
+    for (id, count) in counts.iter() {
+        println!("{:>10}  {:>10}", id, count);
+    }
+}
+
+/// Print the frequency of each year as a table
+fn print_timeline(points: &[(u32, f64)]) {
+    println!("{:>6}  {:>12}", "year", "frequency");
+    for (year, frequency) in points {
+        println!("{:>6}  {:>12.8}", year, frequency);
+    }
+}
+
+/// Print the word offsets of the matches in each document as a table
+fn print_positions(matches: &[(usize, Vec<usize>)]) {
+    println!("{:>10}  offsets", "document");
+    for (id, offsets) in matches {
+        let offsets: Vec<String> = offsets.iter().map(|o| o.to_string()).collect();
+        println!("{:>10}  {}", id, offsets.join(", "));
+    }
+}
+
+/// Print the score of each document as a table, in the order given
+fn print_scores(scores: &[(usize, f64)]) {
+    println!("{:>10}  {:>12}", "document", "score");
+    for (id, score) in scores {
+        println!("{:>10}  {:>12.6}", id, score);
+    }
+}
+
+/// Print each occurrence on its own line, with the occurrences lined up in one column
+fn print_concordance(lines: &[ConcordanceLine]) {
+    let width = lines
+        .iter()
+        .map(|line| line.left.chars().count())
+        .max()
+        .unwrap_or(0);
+    for line in lines {
+        println!(
+            "{:>6}:{:<6}  {:>width$}  {}  {}",
+            line.id,
+            line.offset,
+            line.left,
+            line.keyword,
+            line.right,
+            width = width
+        );
+    }
+}
+
+/// Print the score and excerpt of each document, with the highlighted words in brackets
+fn print_snippets(snippets: &[Snippet]) {
+    for snippet in snippets {
+        let mut text = String::new();
+        let mut last = 0;
+        for range in &snippet.highlights {
+            text.push_str(&snippet.text[last..range.start]);
+            text.push('[');
+            text.push_str(&snippet.text[range.clone()]);
+            text.push(']');
+            last = range.end;
+        }
+        text.push_str(&snippet.text[last..]);
+        println!("{:>10}  {:>12.6}  {}", snippet.id, snippet.score, text);
+    }
+}
+
+/// Print each collocation with its count and scores as a table
+fn print_collocations(collocations: &[Collocation]) {
+    println!(
+        "{:<20}  {:>8}  {:>10}  {:>14}",
+        "word", "count", "pmi", "log-likelihood"
+    );
+    for c in collocations {
+        println!(
+            "{:<20}  {:>8}  {:>10.4}  {:>14.4}",
+            c.word, c.count, c.pmi, c.log_likelihood
+        );
+    }
+}
+
+/// Print the size of the archive followed by its most frequent words
+fn print_stats(stats: &CorpusStats) {
+    println!("Documents:       {}", stats.documents);
+    println!("Words:           {}", stats.tokens);
+    println!("Distinct words:  {}", stats.vocabulary);
+    println!("Average length:  {:.1}", stats.average_length);
+    print_term_counts(&stats.top_terms);
+}
+
+/// Print the size of a document followed by its most frequent words
+fn print_doc_stats(stats: &DocStats) {
+    println!("Document:        {}", stats.id);
+    println!("Words:           {}", stats.tokens);
+    println!("Distinct words:  {}", stats.distinct);
+    print_term_counts(&stats.top_terms);
+}
+
+/// Print words and their number of occurrences as a table
+fn print_term_counts(counts: &[(String, usize)]) {
+    println!();
+    println!("{:<20}  {:>10}", "word", "count");
+    for (term, count) in counts {
+        println!("{:<20}  {:>10}", term, count);
+    }
+}
+
+/// Print words with their frequencies as a table, followed by how many words there are in total
+fn print_terms(terms: &[TermFrequency], total: usize) {
+    println!("{:<20}  {:>10}  {:>12}", "word", "documents", "occurrences");
+    for t in terms {
+        println!("{:<20}  {:>10}  {:>12}", t.term, t.documents, t.occurrences);
+    }
+    println!();
+    println!("{} of {} words", terms.len(), total);
+}
+
+/// Print words with their frequencies as comma separated values, with a header row
+fn print_terms_csv(terms: &[TermFrequency]) {
+    println!("term,documents,occurrences");
+    for t in terms {
+        let term = if t.term.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", t.term.replace('"', "\"\""))
+        } else {
+            t.term.clone()
+        };
+        println!("{},{},{}", term, t.documents, t.occurrences);
+    }
+}
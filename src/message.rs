@@ -1,3 +1,11 @@
+use crate::database::{
+    Association, Collocation, ConcordanceLine, CorpusStats, DocStats, Scoring, Snippet,
+    TermFrequency,
+};
+use crate::varint::{read_sorted, write_sorted};
+use crate::vocabulary::PatternKind;
+use std::io::Read;
+
 /// A request from the client to the server
 #[derive(Debug, PartialEq)]
 pub enum Request {
@@ -7,69 +15,430 @@ pub enum Request {
     Search { word: String },
     /// Retrieve the document with the index `id` from the archive
     Retrieve { id: usize },
+    /// Search for the contiguous sequence of words `ngram` in the archive
+    SearchNgram { ngram: String },
+    /// Count the occurrences of the contiguous sequence of words `ngram` in each document
+    Count { ngram: String },
+    /// Add the document `doc` to the archive with the given title, author and publication year
+    PublishWithMetadata {
+        doc: String,
+        title: String,
+        author: String,
+        year: u32,
+    },
+    /// Get the frequency of `ngram` in each publication year, divided by the number of words
+    /// published that year if `normalize` is set, and averaged over the years up to `smoothing`
+    /// years away
+    Timeline {
+        ngram: String,
+        normalize: bool,
+        smoothing: u32,
+    },
+    /// Search for the documents matching the boolean query `query`, such as
+    /// `whale AND (ship OR boat) NOT ahab`
+    Query { query: String },
+    /// Search for the words of `phrase` next to each other and in order
+    Phrase { phrase: String },
+    /// Search for the phrases `left` and `right` with at most `distance` other words between them
+    Near {
+        left: String,
+        right: String,
+        distance: usize,
+    },
+    /// Search for every word matching the prefix, glob or regular expression `pattern`
+    Pattern { pattern: String, kind: PatternKind },
+    /// Search for every word within `max_distance` single character edits of `word`
+    FuzzySearch { word: String, max_distance: usize },
+    /// Get the `k` documents that best match the words of `query` when scored with `scoring`
+    RankedSearch {
+        query: String,
+        scoring: Scoring,
+        k: usize,
+    },
+    /// Get up to `limit` occurrences of `term` with up to `window` words of context on each side
+    Concordance {
+        term: String,
+        window: usize,
+        limit: usize,
+    },
+    /// Get the `k` documents that best match the words of `query` when scored with `scoring`,
+    /// each with an excerpt of up to `words` words around the words of the query
+    SnippetSearch {
+        query: String,
+        scoring: Scoring,
+        k: usize,
+        words: usize,
+    },
+    /// Get the `k` words most strongly associated with `term` by `measure`, among the words
+    /// occurring at least `min_count` times within `window` words of it
+    Collocations {
+        term: String,
+        window: usize,
+        measure: Association,
+        min_count: usize,
+        k: usize,
+    },
+    /// Get the size of the archive and its `top_k` most frequent words
+    Stats { top_k: usize },
+    /// Get the size of the document with the index `id` and its `top_k` most frequent words
+    DocStats { id: usize, top_k: usize },
+    /// List the words starting with `prefix` with their frequencies, in sorted order, skipping
+    /// the first `offset` and returning at most `limit`
+    ListTerms {
+        prefix: String,
+        offset: usize,
+        limit: usize,
+    },
+    /// Delete the document with the index `id` from the archive
+    Delete { id: usize },
+    /// Replace the text of the document with the index `id` by `doc`, keeping its index
+    Replace { id: usize, doc: String },
+    /// Write a snapshot of the whole archive to the server's storage
+    Snapshot,
+    /// Search for the word `word` in the archive, with the results sent compressed
+    CompressedSearch { word: String },
 }
 impl Request {
     // TODO:
     // Convert the request `self` into a byte vector. See the assignment handout for suggestions on
     // how to represent the request as a series of bytes.
     pub fn to_bytes(&self) -> Vec<u8> {
-        let bytes = match self {
+        match self {
             Request::Publish { doc } => {
                 let mut result = vec![1u8];
-                let doc_bytes = doc.as_bytes();
-                let len = doc_bytes.len() as u32;
-                result.extend_from_slice(&len.to_be_bytes());
-                result.extend_from_slice(doc_bytes);
+                write_string(&mut result, doc);
                 result
             }
             Request::Search { word } => {
                 let mut result = vec![2u8];
-                let word_bytes = word.as_bytes();
-                let len = word_bytes.len() as u32;
-                result.extend_from_slice(&len.to_be_bytes());
-                result.extend_from_slice(word_bytes);
+                write_string(&mut result, word);
                 result
             }
             Request::Retrieve { id } => {
                 let mut result = vec![3u8];
-                result.extend_from_slice(&id.to_be_bytes());
+                write_usize(&mut result, *id);
+                result
+            }
+            Request::SearchNgram { ngram } => {
+                let mut result = vec![4u8];
+                write_string(&mut result, ngram);
+                result
+            }
+            Request::Count { ngram } => {
+                let mut result = vec![5u8];
+                write_string(&mut result, ngram);
+                result
+            }
+            Request::PublishWithMetadata {
+                doc,
+                title,
+                author,
+                year,
+            } => {
+                let mut result = vec![6u8];
+                write_string(&mut result, doc);
+                write_string(&mut result, title);
+                write_string(&mut result, author);
+                write_u32(&mut result, *year);
+                result
+            }
+            Request::Timeline {
+                ngram,
+                normalize,
+                smoothing,
+            } => {
+                let mut result = vec![7u8];
+                write_string(&mut result, ngram);
+                write_bool(&mut result, *normalize);
+                write_u32(&mut result, *smoothing);
+                result
+            }
+            Request::Query { query } => {
+                let mut result = vec![8u8];
+                write_string(&mut result, query);
+                result
+            }
+            Request::Phrase { phrase } => {
+                let mut result = vec![9u8];
+                write_string(&mut result, phrase);
+                result
+            }
+            Request::Near {
+                left,
+                right,
+                distance,
+            } => {
+                let mut result = vec![10u8];
+                write_string(&mut result, left);
+                write_string(&mut result, right);
+                write_usize(&mut result, *distance);
+                result
+            }
+            Request::Pattern { pattern, kind } => {
+                let mut result = vec![11u8];
+                write_string(&mut result, pattern);
+                result.push(match kind {
+                    PatternKind::Prefix => 0,
+                    PatternKind::Glob => 1,
+                    PatternKind::Regex => 2,
+                });
+                result
+            }
+            Request::FuzzySearch { word, max_distance } => {
+                let mut result = vec![12u8];
+                write_string(&mut result, word);
+                write_usize(&mut result, *max_distance);
+                result
+            }
+            Request::RankedSearch { query, scoring, k } => {
+                let mut result = vec![13u8];
+                write_string(&mut result, query);
+                write_scoring(&mut result, scoring);
+                write_usize(&mut result, *k);
+                result
+            }
+            Request::Concordance {
+                term,
+                window,
+                limit,
+            } => {
+                let mut result = vec![14u8];
+                write_string(&mut result, term);
+                write_usize(&mut result, *window);
+                write_usize(&mut result, *limit);
+                result
+            }
+            Request::SnippetSearch {
+                query,
+                scoring,
+                k,
+                words,
+            } => {
+                let mut result = vec![15u8];
+                write_string(&mut result, query);
+                write_scoring(&mut result, scoring);
+                write_usize(&mut result, *k);
+                write_usize(&mut result, *words);
                 result
             }
-        };
-        bytes
+            Request::Collocations {
+                term,
+                window,
+                measure,
+                min_count,
+                k,
+            } => {
+                let mut result = vec![16u8];
+                write_string(&mut result, term);
+                write_usize(&mut result, *window);
+                result.push(match measure {
+                    Association::Pmi => 0,
+                    Association::LogLikelihood => 1,
+                });
+                write_usize(&mut result, *min_count);
+                write_usize(&mut result, *k);
+                result
+            }
+            Request::Stats { top_k } => {
+                let mut result = vec![17u8];
+                write_usize(&mut result, *top_k);
+                result
+            }
+            Request::DocStats { id, top_k } => {
+                let mut result = vec![18u8];
+                write_usize(&mut result, *id);
+                write_usize(&mut result, *top_k);
+                result
+            }
+            Request::ListTerms {
+                prefix,
+                offset,
+                limit,
+            } => {
+                let mut result = vec![19u8];
+                write_string(&mut result, prefix);
+                write_usize(&mut result, *offset);
+                write_usize(&mut result, *limit);
+                result
+            }
+            Request::Delete { id } => {
+                let mut result = vec![20u8];
+                write_usize(&mut result, *id);
+                result
+            }
+            Request::Replace { id, doc } => {
+                let mut result = vec![21u8];
+                write_usize(&mut result, *id);
+                write_string(&mut result, doc);
+                result
+            }
+            Request::Snapshot => vec![22u8],
+            Request::CompressedSearch { word } => {
+                let mut result = vec![23u8];
+                write_string(&mut result, word);
+                result
+            }
+        }
     }
     // TODO:
     // Read a request from `reader` and return it. Calling `to_bytes` from above and then calling
     // `from_bytes` should return the original request. If the request is invalid, return `None`.
-    pub fn from_bytes<R: std::io::Read>(mut reader: R) -> Option<Self> {
-        let mut tag_buf = [0u8; 1];
-        reader.read_exact(&mut tag_buf).ok()?;
-        match tag_buf[0] {
+    pub fn from_bytes<R: Read>(mut reader: R) -> Option<Self> {
+        match read_u8(&mut reader)? {
             1 => {
-                let mut len_buf = [0; 4];
-                reader.read_exact(&mut len_buf).unwrap();
-                let len = u32::from_be_bytes(len_buf) as usize;
-                let mut doc_buf = vec![0; len];
-                reader.read_exact(&mut doc_buf).unwrap();
-                let doc = String::from_utf8(doc_buf).ok()?;
+                let doc = read_string(&mut reader)?;
                 Some(Request::Publish { doc })
             }
             2 => {
-                let mut len_buf = [0; 4];
-                reader.read_exact(&mut len_buf).unwrap();
-                let len = u32::from_be_bytes(len_buf) as usize;
-                let mut word_buf = vec![0; len];
-                reader.read_exact(&mut word_buf).unwrap();
-                let word = String::from_utf8(word_buf).ok()?;
+                let word = read_string(&mut reader)?;
                 Some(Request::Search { word })
             }
             3 => {
-                let mut id_buf = [0u8; 8];
-                reader.read_exact(&mut id_buf).ok()?;
-                let id = usize::from_be_bytes(id_buf);
+                let id = read_usize(&mut reader)?;
                 Some(Request::Retrieve { id })
             }
-
+            4 => {
+                let ngram = read_string(&mut reader)?;
+                Some(Request::SearchNgram { ngram })
+            }
+            5 => {
+                let ngram = read_string(&mut reader)?;
+                Some(Request::Count { ngram })
+            }
+            6 => {
+                let doc = read_string(&mut reader)?;
+                let title = read_string(&mut reader)?;
+                let author = read_string(&mut reader)?;
+                let year = read_u32(&mut reader)?;
+                Some(Request::PublishWithMetadata {
+                    doc,
+                    title,
+                    author,
+                    year,
+                })
+            }
+            7 => {
+                let ngram = read_string(&mut reader)?;
+                let normalize = read_bool(&mut reader)?;
+                let smoothing = read_u32(&mut reader)?;
+                Some(Request::Timeline {
+                    ngram,
+                    normalize,
+                    smoothing,
+                })
+            }
+            8 => {
+                let query = read_string(&mut reader)?;
+                Some(Request::Query { query })
+            }
+            9 => {
+                let phrase = read_string(&mut reader)?;
+                Some(Request::Phrase { phrase })
+            }
+            10 => {
+                let left = read_string(&mut reader)?;
+                let right = read_string(&mut reader)?;
+                let distance = read_usize(&mut reader)?;
+                Some(Request::Near {
+                    left,
+                    right,
+                    distance,
+                })
+            }
+            11 => {
+                let pattern = read_string(&mut reader)?;
+                let kind = match read_u8(&mut reader)? {
+                    0 => PatternKind::Prefix,
+                    1 => PatternKind::Glob,
+                    2 => PatternKind::Regex,
+                    _ => return None,
+                };
+                Some(Request::Pattern { pattern, kind })
+            }
+            12 => {
+                let word = read_string(&mut reader)?;
+                let max_distance = read_usize(&mut reader)?;
+                Some(Request::FuzzySearch { word, max_distance })
+            }
+            13 => {
+                let query = read_string(&mut reader)?;
+                let scoring = read_scoring(&mut reader)?;
+                let k = read_usize(&mut reader)?;
+                Some(Request::RankedSearch { query, scoring, k })
+            }
+            14 => {
+                let term = read_string(&mut reader)?;
+                let window = read_usize(&mut reader)?;
+                let limit = read_usize(&mut reader)?;
+                Some(Request::Concordance {
+                    term,
+                    window,
+                    limit,
+                })
+            }
+            15 => {
+                let query = read_string(&mut reader)?;
+                let scoring = read_scoring(&mut reader)?;
+                let k = read_usize(&mut reader)?;
+                let words = read_usize(&mut reader)?;
+                Some(Request::SnippetSearch {
+                    query,
+                    scoring,
+                    k,
+                    words,
+                })
+            }
+            16 => {
+                let term = read_string(&mut reader)?;
+                let window = read_usize(&mut reader)?;
+                let measure = match read_u8(&mut reader)? {
+                    0 => Association::Pmi,
+                    1 => Association::LogLikelihood,
+                    _ => return None,
+                };
+                let min_count = read_usize(&mut reader)?;
+                let k = read_usize(&mut reader)?;
+                Some(Request::Collocations {
+                    term,
+                    window,
+                    measure,
+                    min_count,
+                    k,
+                })
+            }
+            17 => {
+                let top_k = read_usize(&mut reader)?;
+                Some(Request::Stats { top_k })
+            }
+            18 => {
+                let id = read_usize(&mut reader)?;
+                let top_k = read_usize(&mut reader)?;
+                Some(Request::DocStats { id, top_k })
+            }
+            19 => {
+                let prefix = read_string(&mut reader)?;
+                let offset = read_usize(&mut reader)?;
+                let limit = read_usize(&mut reader)?;
+                Some(Request::ListTerms {
+                    prefix,
+                    offset,
+                    limit,
+                })
+            }
+            20 => {
+                let id = read_usize(&mut reader)?;
+                Some(Request::Delete { id })
+            }
+            21 => {
+                let id = read_usize(&mut reader)?;
+                let doc = read_string(&mut reader)?;
+                Some(Request::Replace { id, doc })
+            }
+            22 => Some(Request::Snapshot),
+            23 => {
+                let word = read_string(&mut reader)?;
+                Some(Request::CompressedSearch { word })
+            }
             _ => None,
         }
     }
@@ -78,8 +447,9 @@ impl Request {
 /// A response from the server to the client
 #[derive(Debug, PartialEq)]
 pub enum Response {
-    /// The document was successfully added to the archive with the given index
-    PublishSuccess(usize),
+    /// The document was successfully added to the archive with the given index, and the indices
+    /// of the documents it nearly duplicates are returned with their estimated similarity
+    PublishSuccess(usize, Vec<(usize, f64)>),
     /// The search for the word was successful, and the indices of the documents containing the
     /// word are returned
     SearchSuccess(Vec<usize>),
@@ -87,77 +457,571 @@ pub enum Response {
     RetrieveSuccess(String),
     /// The request failed
     Failure,
+    /// The count was successful, and the indices of the documents containing the n-gram are
+    /// returned along with the number of times it occurs in each
+    CountSuccess(Vec<(usize, usize)>),
+    /// The timeline query was successful, and each publication year is returned with the
+    /// frequency of the n-gram in that year
+    TimelineSuccess(Vec<(u32, f64)>),
+    /// The request was invalid, for the given reason
+    Error(String),
+    /// The positional search was successful, and the indices of the matching documents are
+    /// returned along with the word offsets at which the matches start
+    PositionsSuccess(Vec<(usize, Vec<usize>)>),
+    /// The pattern search was successful, and the words matching the pattern are returned along
+    /// with the indices of the documents containing any of them
+    PatternSuccess(Vec<String>, Vec<usize>),
+    /// The fuzzy search was successful, and each matching word is returned, closest first, with
+    /// the indices of the documents containing it
+    FuzzySuccess(Vec<(String, Vec<usize>)>),
+    /// The ranked search was successful, and the indices of the best matching documents are
+    /// returned with their scores, from highest to lowest
+    RankedSuccess(Vec<(usize, f64)>),
+    /// The concordance was successful, and each occurrence is returned with its context
+    ConcordanceSuccess(Vec<ConcordanceLine>),
+    /// The ranked search was successful, and the best matching documents are returned with their
+    /// scores and excerpts, from highest to lowest score
+    SnippetSuccess(Vec<Snippet>),
+    /// The collocation query was successful, and the associated words are returned with their
+    /// counts and scores, most strongly associated first
+    CollocationSuccess(Vec<Collocation>),
+    /// The archive statistics were successfully gathered
+    StatsSuccess(CorpusStats),
+    /// The document statistics were successfully gathered
+    DocStatsSuccess(DocStats),
+    /// The listing was successful, and a page of words is returned with their frequencies,
+    /// along with the total number of words matching the request
+    TermsSuccess(Vec<TermFrequency>, usize),
+    /// The document with the given index was successfully deleted
+    DeleteSuccess(usize),
+    /// The document with the given index cannot be retrieved because it was deleted
+    Deleted(usize),
+    /// The text of the document with the given index was successfully replaced
+    ReplaceSuccess(usize),
+    /// The document was not added because it was already published with the given index
+    Duplicate(usize),
+    /// The snapshot with the given generation was successfully written
+    SnapshotSuccess(u64),
+    /// The search for the word was successful, and the indices of the documents containing the
+    /// word are returned in ascending order, written as variable-length gaps between them
+    CompressedSearchSuccess(Vec<usize>),
 }
 impl Response {
     // TODO:
     // Convert the request `self` into a byte vector. See the assignment handout for suggestions on
     // how to represent the request as a series of bytes.
     pub fn to_bytes(&self) -> Vec<u8> {
-        let bytes = match self {
-            Response::PublishSuccess(id) => {
+        match self {
+            Response::PublishSuccess(id, similar) => {
                 let mut result = vec![1u8];
-                result.extend_from_slice(&id.to_be_bytes());
+                write_usize(&mut result, *id);
+                write_u32(&mut result, similar.len() as u32);
+                for (id, similarity) in similar {
+                    write_usize(&mut result, *id);
+                    write_f64(&mut result, *similarity);
+                }
                 result
             }
             Response::SearchSuccess(ids) => {
                 let mut result = vec![2u8];
-                let len = ids.len() as u32;
-                result.extend_from_slice(&len.to_be_bytes());
+                write_u32(&mut result, ids.len() as u32);
                 for id in ids {
-                    result.extend_from_slice(&id.to_be_bytes());
+                    write_usize(&mut result, *id);
                 }
                 result
             }
             Response::RetrieveSuccess(doc) => {
                 let mut result = vec![3u8];
-                let doc_bytes = doc.as_bytes();
-                let len = doc_bytes.len() as u32;
-                result.extend_from_slice(&len.to_be_bytes());
-                result.extend_from_slice(doc_bytes);
+                write_string(&mut result, doc);
                 result
             }
             Response::Failure => vec![4u8],
-        };
-        bytes
+            Response::CountSuccess(counts) => {
+                let mut result = vec![5u8];
+                write_u32(&mut result, counts.len() as u32);
+                for (id, count) in counts {
+                    write_usize(&mut result, *id);
+                    write_usize(&mut result, *count);
+                }
+                result
+            }
+            Response::TimelineSuccess(points) => {
+                let mut result = vec![6u8];
+                write_u32(&mut result, points.len() as u32);
+                for (year, frequency) in points {
+                    write_u32(&mut result, *year);
+                    write_f64(&mut result, *frequency);
+                }
+                result
+            }
+            Response::Error(reason) => {
+                let mut result = vec![7u8];
+                write_string(&mut result, reason);
+                result
+            }
+            Response::PositionsSuccess(matches) => {
+                let mut result = vec![8u8];
+                write_u32(&mut result, matches.len() as u32);
+                for (id, offsets) in matches {
+                    write_usize(&mut result, *id);
+                    write_u32(&mut result, offsets.len() as u32);
+                    for offset in offsets {
+                        write_usize(&mut result, *offset);
+                    }
+                }
+                result
+            }
+            Response::PatternSuccess(terms, ids) => {
+                let mut result = vec![9u8];
+                write_u32(&mut result, terms.len() as u32);
+                for term in terms {
+                    write_string(&mut result, term);
+                }
+                write_u32(&mut result, ids.len() as u32);
+                for id in ids {
+                    write_usize(&mut result, *id);
+                }
+                result
+            }
+            Response::FuzzySuccess(variants) => {
+                let mut result = vec![10u8];
+                write_u32(&mut result, variants.len() as u32);
+                for (term, ids) in variants {
+                    write_string(&mut result, term);
+                    write_u32(&mut result, ids.len() as u32);
+                    for id in ids {
+                        write_usize(&mut result, *id);
+                    }
+                }
+                result
+            }
+            Response::RankedSuccess(scores) => {
+                let mut result = vec![11u8];
+                write_u32(&mut result, scores.len() as u32);
+                for (id, score) in scores {
+                    write_usize(&mut result, *id);
+                    write_f64(&mut result, *score);
+                }
+                result
+            }
+            Response::ConcordanceSuccess(lines) => {
+                let mut result = vec![12u8];
+                write_u32(&mut result, lines.len() as u32);
+                for line in lines {
+                    write_usize(&mut result, line.id);
+                    write_usize(&mut result, line.offset);
+                    write_string(&mut result, &line.left);
+                    write_string(&mut result, &line.keyword);
+                    write_string(&mut result, &line.right);
+                }
+                result
+            }
+            Response::SnippetSuccess(snippets) => {
+                let mut result = vec![13u8];
+                write_u32(&mut result, snippets.len() as u32);
+                for snippet in snippets {
+                    write_usize(&mut result, snippet.id);
+                    write_f64(&mut result, snippet.score);
+                    write_string(&mut result, &snippet.text);
+                    write_u32(&mut result, snippet.highlights.len() as u32);
+                    for range in &snippet.highlights {
+                        write_usize(&mut result, range.start);
+                        write_usize(&mut result, range.end);
+                    }
+                }
+                result
+            }
+            Response::CollocationSuccess(collocations) => {
+                let mut result = vec![14u8];
+                write_u32(&mut result, collocations.len() as u32);
+                for collocation in collocations {
+                    write_string(&mut result, &collocation.word);
+                    write_usize(&mut result, collocation.count);
+                    write_f64(&mut result, collocation.pmi);
+                    write_f64(&mut result, collocation.log_likelihood);
+                }
+                result
+            }
+            Response::StatsSuccess(stats) => {
+                let mut result = vec![15u8];
+                write_usize(&mut result, stats.documents);
+                write_usize(&mut result, stats.tokens);
+                write_usize(&mut result, stats.vocabulary);
+                write_f64(&mut result, stats.average_length);
+                write_term_counts(&mut result, &stats.top_terms);
+                result
+            }
+            Response::DocStatsSuccess(stats) => {
+                let mut result = vec![16u8];
+                write_usize(&mut result, stats.id);
+                write_usize(&mut result, stats.tokens);
+                write_usize(&mut result, stats.distinct);
+                write_term_counts(&mut result, &stats.top_terms);
+                result
+            }
+            Response::TermsSuccess(terms, total) => {
+                let mut result = vec![17u8];
+                write_u32(&mut result, terms.len() as u32);
+                for term in terms {
+                    write_string(&mut result, &term.term);
+                    write_usize(&mut result, term.documents);
+                    write_usize(&mut result, term.occurrences);
+                }
+                write_usize(&mut result, *total);
+                result
+            }
+            Response::DeleteSuccess(id) => {
+                let mut result = vec![18u8];
+                write_usize(&mut result, *id);
+                result
+            }
+            Response::Deleted(id) => {
+                let mut result = vec![19u8];
+                write_usize(&mut result, *id);
+                result
+            }
+            Response::ReplaceSuccess(id) => {
+                let mut result = vec![20u8];
+                write_usize(&mut result, *id);
+                result
+            }
+            Response::Duplicate(id) => {
+                let mut result = vec![21u8];
+                write_usize(&mut result, *id);
+                result
+            }
+            Response::SnapshotSuccess(generation) => {
+                let mut result = vec![22u8];
+                write_u64(&mut result, *generation);
+                result
+            }
+            Response::CompressedSearchSuccess(ids) => {
+                let mut result = vec![23u8];
+                write_sorted(&mut result, ids);
+                result
+            }
+        }
     }
     // TODO:
     // Read a request from `reader` and return it. Calling `to_bytes` from above and then calling
     // `from_bytes` should return the original request. If the request is invalid, return `None`.
-    pub fn from_bytes<R: std::io::Read>(mut reader: R) -> Option<Self> {
-        let mut tag_buf = [0u8; 1];
-        reader.read_exact(&mut tag_buf).ok()?;
-        match tag_buf[0] {
+    pub fn from_bytes<R: Read>(mut reader: R) -> Option<Self> {
+        match read_u8(&mut reader)? {
             1 => {
-                let mut id_buf = [0u8; 8];
-                reader.read_exact(&mut id_buf).ok()?;
-                let id = usize::from_be_bytes(id_buf);
-                Some(Response::PublishSuccess(id))
+                let id = read_usize(&mut reader)?;
+                let len = read_u32(&mut reader)? as usize;
+                let mut similar = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = read_usize(&mut reader)?;
+                    let similarity = read_f64(&mut reader)?;
+                    similar.push((id, similarity));
+                }
+                Some(Response::PublishSuccess(id, similar))
             }
-            
             2 => {
-                let mut len_buf = [0; 4];
-                reader.read_exact(&mut len_buf).unwrap();
-                let len = u32::from_be_bytes(len_buf) as usize;
+                let len = read_u32(&mut reader)? as usize;
                 let mut ids = Vec::with_capacity(len);
                 for _ in 0..len {
-                    let mut id_buf = [0; 8];
-                    reader.read_exact(&mut id_buf).unwrap();
-                    let id = usize::from_be_bytes(id_buf);
-                    ids.push(id);
+                    ids.push(read_usize(&mut reader)?);
                 }
                 Some(Response::SearchSuccess(ids))
             }
             3 => {
-                let mut len_buf = [0; 4];
-                reader.read_exact(&mut len_buf).unwrap();
-                let len = u32::from_be_bytes(len_buf) as usize;
-                let mut doc_buf = vec![0; len];
-                reader.read_exact(&mut doc_buf).unwrap();
-                let doc = String::from_utf8(doc_buf).ok()?;
+                let doc = read_string(&mut reader)?;
                 Some(Response::RetrieveSuccess(doc))
             }
             4 => Some(Response::Failure),
+            5 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut counts = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = read_usize(&mut reader)?;
+                    let count = read_usize(&mut reader)?;
+                    counts.push((id, count));
+                }
+                Some(Response::CountSuccess(counts))
+            }
+            6 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut points = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let year = read_u32(&mut reader)?;
+                    let frequency = read_f64(&mut reader)?;
+                    points.push((year, frequency));
+                }
+                Some(Response::TimelineSuccess(points))
+            }
+            7 => {
+                let reason = read_string(&mut reader)?;
+                Some(Response::Error(reason))
+            }
+            8 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut matches = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = read_usize(&mut reader)?;
+                    let count = read_u32(&mut reader)? as usize;
+                    let mut offsets = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        offsets.push(read_usize(&mut reader)?);
+                    }
+                    matches.push((id, offsets));
+                }
+                Some(Response::PositionsSuccess(matches))
+            }
+            9 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut terms = Vec::with_capacity(len);
+                for _ in 0..len {
+                    terms.push(read_string(&mut reader)?);
+                }
+                let len = read_u32(&mut reader)? as usize;
+                let mut ids = Vec::with_capacity(len);
+                for _ in 0..len {
+                    ids.push(read_usize(&mut reader)?);
+                }
+                Some(Response::PatternSuccess(terms, ids))
+            }
+            10 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut variants = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let term = read_string(&mut reader)?;
+                    let count = read_u32(&mut reader)? as usize;
+                    let mut ids = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        ids.push(read_usize(&mut reader)?);
+                    }
+                    variants.push((term, ids));
+                }
+                Some(Response::FuzzySuccess(variants))
+            }
+            11 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut scores = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = read_usize(&mut reader)?;
+                    let score = read_f64(&mut reader)?;
+                    scores.push((id, score));
+                }
+                Some(Response::RankedSuccess(scores))
+            }
+            12 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut lines = Vec::with_capacity(len);
+                for _ in 0..len {
+                    lines.push(ConcordanceLine {
+                        id: read_usize(&mut reader)?,
+                        offset: read_usize(&mut reader)?,
+                        left: read_string(&mut reader)?,
+                        keyword: read_string(&mut reader)?,
+                        right: read_string(&mut reader)?,
+                    });
+                }
+                Some(Response::ConcordanceSuccess(lines))
+            }
+            13 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut snippets = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = read_usize(&mut reader)?;
+                    let score = read_f64(&mut reader)?;
+                    let text = read_string(&mut reader)?;
+                    let count = read_u32(&mut reader)? as usize;
+                    let mut highlights = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        let start = read_usize(&mut reader)?;
+                        let end = read_usize(&mut reader)?;
+                        highlights.push(start..end);
+                    }
+                    snippets.push(Snippet {
+                        id,
+                        score,
+                        text,
+                        highlights,
+                    });
+                }
+                Some(Response::SnippetSuccess(snippets))
+            }
+            14 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut collocations = Vec::with_capacity(len);
+                for _ in 0..len {
+                    collocations.push(Collocation {
+                        word: read_string(&mut reader)?,
+                        count: read_usize(&mut reader)?,
+                        pmi: read_f64(&mut reader)?,
+                        log_likelihood: read_f64(&mut reader)?,
+                    });
+                }
+                Some(Response::CollocationSuccess(collocations))
+            }
+            15 => Some(Response::StatsSuccess(CorpusStats {
+                documents: read_usize(&mut reader)?,
+                tokens: read_usize(&mut reader)?,
+                vocabulary: read_usize(&mut reader)?,
+                average_length: read_f64(&mut reader)?,
+                top_terms: read_term_counts(&mut reader)?,
+            })),
+            16 => Some(Response::DocStatsSuccess(DocStats {
+                id: read_usize(&mut reader)?,
+                tokens: read_usize(&mut reader)?,
+                distinct: read_usize(&mut reader)?,
+                top_terms: read_term_counts(&mut reader)?,
+            })),
+            17 => {
+                let len = read_u32(&mut reader)? as usize;
+                let mut terms = Vec::with_capacity(len);
+                for _ in 0..len {
+                    terms.push(TermFrequency {
+                        term: read_string(&mut reader)?,
+                        documents: read_usize(&mut reader)?,
+                        occurrences: read_usize(&mut reader)?,
+                    });
+                }
+                let total = read_usize(&mut reader)?;
+                Some(Response::TermsSuccess(terms, total))
+            }
+            18 => {
+                let id = read_usize(&mut reader)?;
+                Some(Response::DeleteSuccess(id))
+            }
+            19 => {
+                let id = read_usize(&mut reader)?;
+                Some(Response::Deleted(id))
+            }
+            20 => {
+                let id = read_usize(&mut reader)?;
+                Some(Response::ReplaceSuccess(id))
+            }
+            21 => {
+                let id = read_usize(&mut reader)?;
+                Some(Response::Duplicate(id))
+            }
+            22 => {
+                let generation = read_u64(&mut reader)?;
+                Some(Response::SnapshotSuccess(generation))
+            }
+            23 => {
+                let ids = read_sorted(&mut reader)?;
+                Some(Response::CompressedSearchSuccess(ids))
+            }
             _ => None,
         }
     }
 }
+
+// Numbers are written big endian, and strings are written as their length as a `u32` followed
+// by their UTF-8 bytes. Every reader returns `None` if the input ends early or is malformed.
+
+pub(crate) fn write_bool(bytes: &mut Vec<u8>, b: bool) {
+    bytes.push(b as u8);
+}
+
+pub(crate) fn write_u32(bytes: &mut Vec<u8>, n: u32) {
+    bytes.extend_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn write_usize(bytes: &mut Vec<u8>, n: usize) {
+    bytes.extend_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn write_u64(bytes: &mut Vec<u8>, n: u64) {
+    bytes.extend_from_slice(&n.to_be_bytes());
+}
+
+fn write_f64(bytes: &mut Vec<u8>, x: f64) {
+    bytes.extend_from_slice(&x.to_be_bytes());
+}
+
+fn write_scoring(bytes: &mut Vec<u8>, scoring: &Scoring) {
+    match scoring {
+        Scoring::TfIdf => bytes.push(0),
+        Scoring::Bm25 { k1, b } => {
+            bytes.push(1);
+            write_f64(bytes, *k1);
+            write_f64(bytes, *b);
+        }
+    }
+}
+
+fn write_term_counts(bytes: &mut Vec<u8>, counts: &[(String, usize)]) {
+    write_u32(bytes, counts.len() as u32);
+    for (term, count) in counts {
+        write_string(bytes, term);
+        write_usize(bytes, *count);
+    }
+}
+
+pub(crate) fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    write_u32(bytes, s.len() as u32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+pub(crate) fn read_u8<R: Read>(reader: &mut R) -> Option<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf).ok()?;
+    Some(buf[0])
+}
+
+pub(crate) fn read_bool<R: Read>(reader: &mut R) -> Option<bool> {
+    match read_u8(reader)? {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u32::from_be_bytes(buf))
+}
+
+pub(crate) fn read_usize<R: Read>(reader: &mut R) -> Option<usize> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(usize::from_be_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u64::from_be_bytes(buf))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Option<f64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(f64::from_be_bytes(buf))
+}
+
+fn read_scoring<R: Read>(reader: &mut R) -> Option<Scoring> {
+    match read_u8(reader)? {
+        0 => Some(Scoring::TfIdf),
+        1 => {
+            let k1 = read_f64(reader)?;
+            let b = read_f64(reader)?;
+            Some(Scoring::Bm25 { k1, b })
+        }
+        _ => None,
+    }
+}
+
+fn read_term_counts<R: Read>(reader: &mut R) -> Option<Vec<(String, usize)>> {
+    let len = read_u32(reader)? as usize;
+    let mut counts = Vec::with_capacity(len);
+    for _ in 0..len {
+        let term = read_string(reader)?;
+        let count = read_usize(reader)?;
+        counts.push((term, count));
+    }
+    Some(counts)
+}
+
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> Option<String> {
+    let len = read_u32(reader)? as usize;
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf).ok()?;
+    String::from_utf8(buf).ok()
+}
@@ -66,4 +66,50 @@ impl<K: Hash + Eq, V: Clone + Eq> ConcurrentMultiMap<K, V> {
         }
         values
     }
-}
\ No newline at end of file
+
+    /// Call `f` with the value associated with `key`, or `None` if there is none, and associate
+    /// `key` with whatever value `f` leaves in its place, or with no value if it leaves `None`,
+    /// all under one lock. Meant for maps that hold at most one value per key.
+    pub fn update<F>(&self, key: K, f: F)
+    where
+        F: FnOnce(&mut Option<V>),
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let bucket_index = hash as usize % self.buckets.len();
+        let mut bucket = self.buckets[bucket_index].write().unwrap();
+        let mut value = match bucket.iter().position(|(k, _)| k == &key) {
+            Some(index) => {
+                let mut rest = bucket.split_off(index);
+                let (_, value) = rest.pop_front().unwrap();
+                bucket.append(&mut rest);
+                Some(value)
+            }
+            None => None,
+        };
+        f(&mut value);
+        if let Some(value) = value {
+            bucket.push_back((key, value));
+        }
+    }
+
+    /// Call `f` with every key-value pair in the map. Each bucket is read-locked while its pairs
+    /// are visited, so pairs set concurrently may or may not be seen, and `f` must not modify
+    /// the map.
+    pub fn for_each<F: FnMut(&K, &V)>(&self, mut f: F) {
+        for bucket in &self.buckets {
+            let bucket = bucket.read().unwrap();
+            for (key, value) in bucket.iter() {
+                f(key, value);
+            }
+        }
+    }
+
+    /// Remove every key-value pair from the map, write-locking one bucket at a time
+    pub fn clear(&self) {
+        for bucket in &self.buckets {
+            bucket.write().unwrap().clear();
+        }
+    }
+}
@@ -9,6 +9,7 @@ use std::{
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
 struct Worker {
+    #[allow(dead_code)]
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
@@ -0,0 +1,237 @@
+use std::fmt;
+
+// Queries combine search terms with the operators AND, OR and NOT, grouped with parentheses:
+//
+//     whale AND (ship OR boat) NOT ahab
+//
+// Operators must be written in uppercase, so a lowercase "and" is an ordinary search term. Terms
+// written next to each other without an operator must all match, so `a NOT b` reads as
+// `a AND NOT b`. NOT binds tightest, then AND, then OR.
+//
+// Words in double quotes, like `"to be or not to be"`, must appear next to each other and in
+// order. Two terms or phrases joined by `NEAR/k`, like `whale NEAR/5 "white shark"`, must appear
+// with at most k other words between them.
+
+/// A boolean combination of search terms
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    /// Documents containing the term
+    Term(String),
+    /// Documents containing the words of the phrase next to each other and in order
+    Phrase(String),
+    /// Documents containing both phrases with at most `distance` other words between them
+    Near {
+        left: String,
+        right: String,
+        distance: usize,
+    },
+    /// Documents matching both queries
+    And(Box<Query>, Box<Query>),
+    /// Documents matching either query
+    Or(Box<Query>, Box<Query>),
+    /// Documents not matching the query
+    Not(Box<Query>),
+}
+
+/// The reason a query could not be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The query has no terms
+    Empty,
+    /// The query ended where a term or `(` was expected
+    UnexpectedEnd,
+    /// The token was found where a term or `(` was expected
+    Unexpected(String),
+    /// A `(` was never closed
+    UnclosedParenthesis,
+    /// A `"` was never closed
+    UnclosedQuote,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "the query is empty"),
+            ParseError::UnexpectedEnd => write!(f, "the query ended early"),
+            ParseError::Unexpected(token) => write!(f, "unexpected `{}`", token),
+            ParseError::UnclosedParenthesis => write!(f, "a `(` is never closed"),
+            ParseError::UnclosedQuote => write!(f, "a `\"` is never closed"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Near(usize),
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::Near(distance) => write!(f, "NEAR/{}", distance),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(phrase) => write!(f, "\"{}\"", phrase),
+        }
+    }
+}
+
+impl Query {
+    /// Parse a query written with AND, OR, NOT and parentheses
+    pub fn parse(input: &str) -> Result<Query, ParseError> {
+        let tokens = lex(input)?;
+        if tokens.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let mut parser = Parser { tokens, next: 0 };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(ParseError::Unexpected(token.to_string())),
+        }
+    }
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, ParseError> {
+    fn flush(word: &mut String, tokens: &mut Vec<Token>) {
+        if word.is_empty() {
+            return;
+        }
+        let near = word.strip_prefix("NEAR/").and_then(|k| k.parse().ok());
+        tokens.push(match (word.as_str(), near) {
+            (_, Some(distance)) => Token::Near(distance),
+            ("AND", _) => Token::And,
+            ("OR", _) => Token::Or,
+            ("NOT", _) => Token::Not,
+            _ => Token::Word(word.clone()),
+        });
+        word.clear();
+    }
+
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | ')' => {
+                flush(&mut word, &mut tokens);
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                flush(&mut word, &mut tokens);
+                let mut phrase = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => phrase.push(c),
+                        None => return Err(ParseError::UnclosedQuote),
+                    }
+                }
+                tokens.push(Token::Quoted(phrase));
+            }
+            c if c.is_whitespace() => flush(&mut word, &mut tokens),
+            c => word.push(c),
+        }
+    }
+    flush(&mut word, &mut tokens);
+    Ok(tokens)
+}
+
+/// A recursive descent parser with one function per level of operator precedence
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn peek_near(&self) -> Option<usize> {
+        match self.peek() {
+            Some(Token::Near(distance)) => Some(*distance),
+            _ => None,
+        }
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, ParseError> {
+        let mut query = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, ParseError> {
+        let mut query = self.not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.advance();
+                }
+                Some(Token::Word(_) | Token::Quoted(_) | Token::Not | Token::Open) => {}
+                _ => return Ok(query),
+            }
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Query, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Query, ParseError> {
+        match self.advance() {
+            Some(Token::Word(left) | Token::Quoted(left)) if self.peek_near().is_some() => {
+                let distance = self.peek_near().unwrap();
+                self.advance();
+                match self.advance() {
+                    Some(Token::Word(right) | Token::Quoted(right)) => Ok(Query::Near {
+                        left,
+                        right,
+                        distance,
+                    }),
+                    Some(token) => Err(ParseError::Unexpected(token.to_string())),
+                    None => Err(ParseError::UnexpectedEnd),
+                }
+            }
+            Some(Token::Word(word)) => Ok(Query::Term(word)),
+            Some(Token::Quoted(phrase)) => Ok(Query::Phrase(phrase)),
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(query),
+                    None => Err(ParseError::UnclosedParenthesis),
+                    Some(token) => Err(ParseError::Unexpected(token.to_string())),
+                }
+            }
+            Some(token) => Err(ParseError::Unexpected(token.to_string())),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::database::Posting;
+use crate::message::{read_u32, read_u64, write_u32, write_u64};
+use crate::varint::{read_sorted, read_varint, write_sorted, write_varint};
+use memmap2::Mmap;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::iter::Peekable;
+use std::path::Path;
+
+// A segment is an immutable file holding part of the reverse index: a run of keys in sorted
+// order, each followed by its postings in ascending order of document id. The file is memory
+// mapped, so only the pages that are actually read are loaded, and a key is found by binary
+// search over a table at the end of the file that gives the offset of every key. The postings of
+// a key are written as the gaps between their document ids, each followed by its positions as a
+// sorted list, all as variable-length integers.
+//
+//     magic, version
+//     key, postings    for every key
+//     offset, length   of every key, in the table
+//     key count, table offset
+
+const MAGIC: &[u8; 8] = b"NGRAMSEG";
+const VERSION: u32 = 2;
+const HEADER: usize = 12;
+const FOOTER: usize = 16;
+/// The size of an entry of the table: the offset of a key and its length
+const ENTRY: usize = 12;
+
+/// An immutable, sorted run of the reverse index stored in a file
+pub struct Segment {
+    map: Mmap,
+    /// The number of keys in the segment
+    len: usize,
+    /// The offset of the table of keys
+    table: usize,
+}
+
+impl Segment {
+    /// Write the keys and postings in `entries`, which must be in ascending order of key, to a
+    /// new segment at `path`. The segment is written to a temporary file first and renamed into
+    /// place, so the file at `path` is always complete.
+    pub fn write<I>(path: &Path, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (String, Vec<Posting>)>,
+    {
+        let temporary = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&temporary)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_be_bytes())?;
+        let mut offset = HEADER;
+        let mut table = Vec::new();
+        let mut bytes = Vec::new();
+        for (key, postings) in entries {
+            write_u64(&mut table, offset as u64);
+            write_u32(&mut table, key.len() as u32);
+            bytes.clear();
+            bytes.extend_from_slice(key.as_bytes());
+            write_varint(&mut bytes, postings.len() as u64);
+            let mut previous = 0;
+            for posting in &postings {
+                write_varint(&mut bytes, (posting.id - previous) as u64);
+                write_sorted(&mut bytes, &posting.positions);
+                previous = posting.id;
+            }
+            out.write_all(&bytes)?;
+            offset += bytes.len();
+        }
+        out.write_all(&table)?;
+        out.write_all(&((table.len() / ENTRY) as u64).to_be_bytes())?;
+        out.write_all(&(offset as u64).to_be_bytes())?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Map the segment at `path` into memory
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: segments are never modified once written, only replaced by renaming a new file
+        // over them or removed, neither of which changes a mapping that is already made
+        let map = unsafe { Mmap::map(&file)? };
+        Self::check(map).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the segment {} is corrupt", path.display()),
+            )
+        })
+    }
+
+    /// Check the header, footer and table of a mapped segment
+    fn check(map: Mmap) -> Option<Self> {
+        if map.len() < HEADER + FOOTER || &map[..8] != MAGIC {
+            return None;
+        }
+        let mut reader = &map[8..];
+        if read_u32(&mut reader)? != VERSION {
+            return None;
+        }
+        let mut footer = &map[map.len() - FOOTER..];
+        let len = usize::try_from(read_u64(&mut footer)?).ok()?;
+        let table = usize::try_from(read_u64(&mut footer)?).ok()?;
+        let footer = map.len() - FOOTER;
+        if table < HEADER || table > footer || len.checked_mul(ENTRY)? != footer - table {
+            return None;
+        }
+        let segment = Self { map, len, table };
+        // Each key must start after the previous one, with its postings in between
+        let mut previous = HEADER;
+        for i in 0..len {
+            let (offset, length) = segment.entry(i);
+            if offset < previous || (i == 0 && offset != HEADER) || offset + length > table {
+                return None;
+            }
+            std::str::from_utf8(&segment.map[offset..offset + length]).ok()?;
+            previous = offset + length;
+        }
+        Some(segment)
+    }
+
+    /// The number of keys in the segment
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the segment has no keys
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The offset and length of the `i`th key
+    fn entry(&self, i: usize) -> (usize, usize) {
+        let mut reader = &self.map[self.table + i * ENTRY..];
+        let offset = read_u64(&mut reader).unwrap_or(0) as usize;
+        let length = read_u32(&mut reader).unwrap_or(0) as usize;
+        (offset, length)
+    }
+
+    /// The offset just past the postings of the `i`th key
+    fn end(&self, i: usize) -> usize {
+        if i + 1 < self.len {
+            self.entry(i + 1).0
+        } else {
+            self.table
+        }
+    }
+
+    fn key(&self, i: usize) -> &str {
+        let (offset, length) = self.entry(i);
+        std::str::from_utf8(&self.map[offset..offset + length]).unwrap_or_default()
+    }
+
+    fn postings(&self, i: usize) -> Vec<Posting> {
+        let (offset, length) = self.entry(i);
+        let mut reader = &self.map[offset + length..self.end(i)];
+        read_postings(&mut reader).unwrap_or_default()
+    }
+
+    /// Get the postings of `key`, in ascending order of document id
+    pub fn get(&self, key: &str) -> Vec<Posting> {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.key(middle).cmp(key) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.postings(middle),
+            }
+        }
+        Vec::new()
+    }
+
+    /// Iterate over every key and its postings, in ascending order of key
+    pub fn iter(&self) -> impl Iterator<Item = (String, Vec<Posting>)> + '_ {
+        (0..self.len).map(|i| (self.key(i).to_string(), self.postings(i)))
+    }
+
+    /// Iterate over the keys starting with `prefix` and their postings, in ascending order of key
+    pub fn with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (String, Vec<Posting>)> + 'a {
+        // The first key not less than `prefix`
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.key(middle) < prefix {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        (low..self.len)
+            .take_while(move |&i| self.key(i).starts_with(prefix))
+            .map(|i| (self.key(i).to_string(), self.postings(i)))
+    }
+}
+
+fn read_postings(reader: &mut &[u8]) -> Option<Vec<Posting>> {
+    let count = usize::try_from(read_varint(reader)?).ok()?;
+    let mut postings = Vec::with_capacity(count.min(reader.len()));
+    let mut id = 0usize;
+    for _ in 0..count {
+        id = id.checked_add(usize::try_from(read_varint(reader)?).ok()?)?;
+        let positions = read_sorted(reader)?;
+        postings.push(Posting { id, positions });
+    }
+    Some(postings)
+}
+
+/// Merge the keys and postings of `segments`, leaving out the postings of the documents in the
+/// set paired with each segment, and yield them in ascending order of key
+pub fn merge<'a>(
+    segments: &'a [(&'a Segment, &'a HashSet<usize>)],
+) -> impl Iterator<Item = (String, Vec<Posting>)> + 'a {
+    Merge {
+        sources: segments
+            .iter()
+            .map(|&(segment, masked)| (segment.iter().peekable(), masked))
+            .collect(),
+    }
+}
+
+type Source<'a, I> = (Peekable<I>, &'a HashSet<usize>);
+
+struct Merge<'a, I: Iterator<Item = (String, Vec<Posting>)>> {
+    sources: Vec<Source<'a, I>>,
+}
+
+impl<I: Iterator<Item = (String, Vec<Posting>)>> Iterator for Merge<'_, I> {
+    type Item = (String, Vec<Posting>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self
+                .sources
+                .iter_mut()
+                .filter_map(|(source, _)| source.peek().map(|(key, _)| key.clone()))
+                .min()?;
+            let mut postings = Vec::new();
+            for (source, masked) in &mut self.sources {
+                if let Some((_, found)) = source.next_if(|(k, _)| *k == key) {
+                    postings.extend(found.into_iter().filter(|p| !masked.contains(&p.id)));
+                }
+            }
+            if !postings.is_empty() {
+                postings.sort_by_key(|p| p.id);
+                return Some((key, postings));
+            }
+        }
+    }
+}
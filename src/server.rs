@@ -1,6 +1,7 @@
-use crate::database::Database;
+use crate::database::{Database, Metadata, PublishError, Published, TimelineOptions};
 use crate::message::*;
 use crate::pool::ThreadPool;
+use crate::query::Query;
 use std::io::Write;
 use std::net::{TcpListener, TcpStream};
 //use std::os::macos::raw::stat;
@@ -9,10 +10,20 @@ use std::sync::{
     Arc,
 };
 use std::thread;
+use std::time::{Duration, Instant};
 
 /// The number of workers in the server's thread pool
 const WORKERS: usize = 16;
 
+/// The response to a request to publish a document
+fn published(result: Result<Published, PublishError>) -> Response {
+    match result {
+        Ok(published) => Response::PublishSuccess(published.id, published.near_duplicates),
+        Err(PublishError::Duplicate(e)) => Response::Duplicate(e.existing),
+        Err(PublishError::Io(e)) => Response::Error(format!("Failed to write to the log: {}", e)),
+    }
+}
+
 // TODO:
 // Implement the `process_message` function. This function should take a `ServerState`, a `Request`,
 // and a `TcpStream`. It should process the request and write the response to the stream.
@@ -21,10 +32,7 @@ const WORKERS: usize = 16;
 // the stream by calling the `write_all` method.
 fn process_message(state: Arc<ServerState>, request: Request, mut stream: TcpStream) {
     let response = match request {
-        Request::Publish { doc } => {
-            let id = state.database.publish(doc);
-            Response::PublishSuccess(id)
-        }
+        Request::Publish { doc } => published(state.database.publish_checked(doc, None)),
         Request::Search { word } => {
             let ids = state.database.search(&word);
             Response::SearchSuccess(ids)
@@ -32,10 +40,127 @@ fn process_message(state: Arc<ServerState>, request: Request, mut stream: TcpStr
         Request::Retrieve { id } => {
             if let Some(doc) = state.database.retrieve(id) {
                 Response::RetrieveSuccess(doc)
+            } else if state.database.is_deleted(id) {
+                Response::Deleted(id)
             } else {
                 Response::Failure
             }
         }
+        Request::SearchNgram { ngram } => {
+            let ids = state.database.search_ngram(&ngram);
+            Response::SearchSuccess(ids)
+        }
+        Request::Count { ngram } => {
+            let counts = state.database.count_ngram(&ngram);
+            Response::CountSuccess(counts)
+        }
+        Request::PublishWithMetadata {
+            doc,
+            title,
+            author,
+            year,
+        } => {
+            let metadata = Metadata {
+                title,
+                author,
+                year: Some(year),
+            };
+            published(state.database.publish_checked(doc, Some(metadata)))
+        }
+        Request::Timeline {
+            ngram,
+            normalize,
+            smoothing,
+        } => {
+            let options = TimelineOptions {
+                normalize,
+                smoothing,
+            };
+            let points = state.database.timeline(&ngram, options);
+            Response::TimelineSuccess(points)
+        }
+        Request::Query { query } => match Query::parse(&query) {
+            Ok(query) => Response::SearchSuccess(state.database.query(&query)),
+            Err(e) => Response::Error(format!("invalid query: {}", e)),
+        },
+        Request::Phrase { phrase } => {
+            let matches = state.database.phrase(&phrase);
+            Response::PositionsSuccess(matches)
+        }
+        Request::Near {
+            left,
+            right,
+            distance,
+        } => {
+            let matches = state.database.near(&left, &right, distance);
+            Response::PositionsSuccess(matches)
+        }
+        Request::Pattern { pattern, kind } => match state.database.search_pattern(kind, &pattern) {
+            Ok((terms, ids)) => Response::PatternSuccess(terms, ids),
+            Err(e) => Response::Error(format!("invalid pattern: {}", e)),
+        },
+        Request::FuzzySearch { word, max_distance } => {
+            let variants = state.database.fuzzy_search(&word, max_distance);
+            Response::FuzzySuccess(variants)
+        }
+        Request::RankedSearch { query, scoring, k } => {
+            Response::RankedSuccess(state.database.ranked_search(&query, scoring, k))
+        }
+        Request::Concordance {
+            term,
+            window,
+            limit,
+        } => Response::ConcordanceSuccess(state.database.concordance(&term, window, limit)),
+        Request::SnippetSearch {
+            query,
+            scoring,
+            k,
+            words,
+        } => Response::SnippetSuccess(state.database.snippets(&query, scoring, k, words)),
+        Request::Collocations {
+            term,
+            window,
+            measure,
+            min_count,
+            k,
+        } => {
+            let collocations = state
+                .database
+                .collocations(&term, window, measure, min_count, k);
+            Response::CollocationSuccess(collocations)
+        }
+        Request::Stats { top_k } => Response::StatsSuccess(state.database.stats(top_k)),
+        Request::DocStats { id, top_k } => match state.database.doc_stats(id, top_k) {
+            Some(stats) => Response::DocStatsSuccess(stats),
+            None => Response::Failure,
+        },
+        Request::ListTerms {
+            prefix,
+            offset,
+            limit,
+        } => {
+            let (terms, total) = state.database.list_terms(&prefix, offset, limit);
+            Response::TermsSuccess(terms, total)
+        }
+        Request::Delete { id } => match state.database.delete(id) {
+            Ok(true) => Response::DeleteSuccess(id),
+            Ok(false) => Response::Failure,
+            Err(e) => Response::Error(format!("Failed to write to the log: {}", e)),
+        },
+        Request::Replace { id, doc } => match state.database.replace(id, doc) {
+            Ok(true) => Response::ReplaceSuccess(id),
+            Ok(false) if state.database.is_deleted(id) => Response::Deleted(id),
+            Ok(false) => Response::Failure,
+            Err(e) => Response::Error(format!("Failed to write to the log: {}", e)),
+        },
+        Request::CompressedSearch { word } => {
+            let ids = state.database.search(&word);
+            Response::CompressedSearchSuccess(ids)
+        }
+        Request::Snapshot => match state.database.snapshot() {
+            Ok(generation) => Response::SnapshotSuccess(generation),
+            Err(e) => Response::Error(format!("Failed to write a snapshot: {}", e)),
+        },
     };
     let _ = stream.write_all(&response.to_bytes());
 }
@@ -50,9 +175,9 @@ struct ServerState {
     is_stopped: AtomicBool,
 }
 impl ServerState {
-    fn new() -> Self {
+    fn new(database: Database) -> Self {
         Self {
-            database: Database::new(),
+            database,
             pool: ThreadPool::new(WORKERS),
             is_stopped: AtomicBool::new(false),
         }
@@ -61,16 +186,36 @@ impl ServerState {
 
 pub struct Server {
     state: Arc<ServerState>,
+    /// How often the database is snapshotted while the server runs, if at all
+    snapshot_interval: Option<Duration>,
+}
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Server {
     // TODO:
     // Create a new server by using the `ServerState::new` function
     pub fn new() -> Self {
+        Self::with_database(Database::new())
+    }
+
+    /// Create a new server that serves requests from the given `database`
+    pub fn with_database(database: Database) -> Self {
         Self {
-            state: Arc::new(ServerState::new()),
+            state: Arc::new(ServerState::new(database)),
+            snapshot_interval: None,
         }
     }
 
+    /// Write a snapshot of the database every `interval` while the server runs. The database
+    /// must have storage.
+    pub fn with_snapshot_interval(mut self, interval: Duration) -> Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
     // TODO:
     // Spawn a thread that listens for incoming connections on the given port. When a connection is
     // established, add a task to the thread pool that deserializes the request, and processes it
@@ -114,16 +259,45 @@ impl Server {
         let _ = ctrlc::try_set_handler(move || {
             state.is_stopped.store(true, Ordering::SeqCst);
         });
-        
+
+        if let Some(interval) = self.snapshot_interval {
+            let state = Arc::clone(&self.state);
+            thread::spawn(move || snapshot_periodically(&state, interval));
+        }
+        let state = Arc::clone(&self.state);
+        thread::spawn(move || merge_periodically(&state));
 
         // TODO: Call the listen function and then loop (doing nothing) until the server has been stopped
         self.listen(port);
         while !self.state.is_stopped.load(Ordering::SeqCst) {
             thread::sleep(std::time::Duration::from_millis(100));
         }
-       
     }
     pub fn stop(&self) {
         self.state.is_stopped.store(true, Ordering::SeqCst);
     }
 }
+
+/// Write a snapshot of the database every `interval` until the server is stopped
+fn snapshot_periodically(state: &ServerState, interval: Duration) {
+    let mut last = Instant::now();
+    while !state.is_stopped.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(100));
+        if last.elapsed() >= interval {
+            if let Err(e) = state.database.snapshot() {
+                eprintln!("Failed to write a snapshot: {}", e);
+            }
+            last = Instant::now();
+        }
+    }
+}
+
+/// Merge the segments of the database whenever there are too many, until the server is stopped
+fn merge_periodically(state: &ServerState) {
+    while !state.is_stopped.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_secs(1));
+        if let Err(e) = state.database.merge_segments() {
+            eprintln!("Failed to merge segments: {}", e);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use crate::database::Metadata;
+use crate::dedup::hash_bytes;
+use crate::message::{
+    read_bool, read_string, read_u32, read_u64, read_u8, read_usize, write_bool, write_string,
+    write_u32, write_u64, write_usize,
+};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Every change to a database with storage is appended to a write-ahead log before it is applied,
+// and the log is replayed into an empty database on startup. Each record is framed by its length
+// and a hash of its contents, so a record cut short by a crash is recognised and dropped, along
+// with anything after it, when the log is next opened.
+//
+// A snapshot holds the whole database as of some point, so that it can be loaded without
+// replaying every change since the beginning. Snapshots are numbered by generation, and each
+// generation has its own log holding the changes made after its snapshot. A new snapshot is
+// written to a temporary file and renamed into place before the log of the next generation is
+// started, so a crash at any point leaves a snapshot and a log that agree with each other.
+//
+// The directory also holds the segments of the reverse index. Flushing the postings in memory to
+// a new segment doesn't take a snapshot, which would write out every document again. Instead a
+// `Flush` record naming the segment is appended to the log, and then a small index manifest
+// listing every segment is written in place of the last one. The manifest of the current
+// generation takes the place of the segment list in the snapshot, and the postings of the
+// changes logged before the `Flush` record it names are in its segments, so those changes don't
+// need to be indexed again when the log is replayed. A crash between the two leaves a `Flush`
+// record no manifest names, which is ignored. A segment is written before the snapshot or
+// manifest that lists it, and removed only once neither does.
+
+/// A change to the database, as stored in the log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Record {
+    /// The document `doc` was published with the index `id` and the given metadata
+    Publish {
+        id: usize,
+        doc: String,
+        metadata: Option<Metadata>,
+    },
+    /// The document with the index `id` was deleted
+    Delete { id: usize },
+    /// The text of the document with the index `id` was replaced by `doc`
+    Replace { id: usize, doc: String },
+    /// The postings of every change before this one were flushed to the segment stored under
+    /// `segment`
+    Flush { segment: u64 },
+}
+
+impl Record {
+    /// Convert the record into a byte vector
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Record::Publish { id, doc, metadata } => {
+                let mut result = vec![1u8];
+                write_usize(&mut result, *id);
+                write_string(&mut result, doc);
+                write_metadata(&mut result, metadata);
+                result
+            }
+            Record::Delete { id } => {
+                let mut result = vec![2u8];
+                write_usize(&mut result, *id);
+                result
+            }
+            Record::Replace { id, doc } => {
+                let mut result = vec![3u8];
+                write_usize(&mut result, *id);
+                write_string(&mut result, doc);
+                result
+            }
+            Record::Flush { segment } => {
+                let mut result = vec![4u8];
+                write_u64(&mut result, *segment);
+                result
+            }
+        }
+    }
+
+    /// Read a record from `reader`, or return `None` if it is invalid
+    pub fn from_bytes<R: Read>(mut reader: R) -> Option<Self> {
+        match read_u8(&mut reader)? {
+            1 => {
+                let id = read_usize(&mut reader)?;
+                let doc = read_string(&mut reader)?;
+                let metadata = read_metadata(&mut reader)?;
+                Some(Record::Publish { id, doc, metadata })
+            }
+            2 => {
+                let id = read_usize(&mut reader)?;
+                Some(Record::Delete { id })
+            }
+            3 => {
+                let id = read_usize(&mut reader)?;
+                let doc = read_string(&mut reader)?;
+                Some(Record::Replace { id, doc })
+            }
+            4 => {
+                let segment = read_u64(&mut reader)?;
+                Some(Record::Flush { segment })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An append-only file of records
+pub struct Log {
+    file: File,
+    /// The length of the file up to the end of the last record appended successfully
+    len: u64,
+    /// Whether a failed append left part of a record in the file that couldn't be removed
+    broken: bool,
+}
+
+impl Log {
+    /// Open the log at `path`, creating it if it doesn't exist, and read every complete record in
+    /// it. An incomplete or corrupt record at the end of the file is removed.
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<Record>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut records = Vec::new();
+        let mut valid = 0;
+        let mut rest = &bytes[..];
+        while let Some((record, len)) = read_frame(rest) {
+            records.push(record);
+            valid += len;
+            rest = &rest[len..];
+        }
+        if valid < bytes.len() {
+            file.set_len(valid as u64)?;
+            file.seek(SeekFrom::End(0))?;
+        }
+        let log = Self {
+            file,
+            len: valid as u64,
+            broken: false,
+        };
+        Ok((log, records))
+    }
+
+    /// Append `record` to the log and wait for it to reach the disk. If that fails, whatever
+    /// part of the record was written is removed, since a torn record would hide every record
+    /// after it when the log is next opened. If it can't be removed, every later append fails.
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        if self.broken {
+            return Err(io::Error::other(
+                "the log is unusable after a write to it failed",
+            ));
+        }
+        let payload = record.to_bytes();
+        let mut frame = Vec::with_capacity(payload.len() + 12);
+        write_u32(&mut frame, payload.len() as u32);
+        frame.extend_from_slice(&hash_bytes(&payload).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        let written = self
+            .file
+            .write_all(&frame)
+            .and_then(|()| self.file.sync_data());
+        match written {
+            Ok(()) => {
+                self.len += frame.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                if self.file.set_len(self.len).is_err() {
+                    self.broken = true;
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"NGRAMSNP";
+const SNAPSHOT_VERSION: u32 = 2;
+const MANIFEST_MAGIC: &[u8; 8] = b"NGRAMIDX";
+const MANIFEST_VERSION: u32 = 1;
+
+/// A storage directory holding the latest snapshot and the log of changes made since
+pub struct Storage {
+    dir: PathBuf,
+    /// The generation of the latest snapshot, or 0 if there is none
+    generation: u64,
+    log: Log,
+    /// The segment named by the last `Flush` record of the current generation that a manifest
+    /// has been written for
+    flushed: Option<u64>,
+    /// The contents of the manifest of the current generation and the number of records at the
+    /// start of the log it covers, until they are taken by `take_manifest`
+    manifest: Option<(Vec<u8>, usize)>,
+    /// The numbers of the segments being written that aren't yet listed in a snapshot or
+    /// manifest
+    pending: HashSet<u64>,
+}
+
+impl Storage {
+    /// The name of the latest snapshot within a storage directory
+    pub const SNAPSHOT: &'static str = "snapshot.bin";
+
+    /// The name of the index manifest within a storage directory
+    pub const MANIFEST: &'static str = "index.bin";
+
+    /// The name of the log of the given generation within a storage directory
+    pub fn log_name(generation: u64) -> String {
+        format!("wal-{}.log", generation)
+    }
+
+    /// Open the storage in `dir`, creating the directory if needed. Return the contents of the
+    /// latest snapshot, as written by the closure passed to `snapshot`, if there is one, along
+    /// with the records of the changes made after it.
+    pub fn open(dir: &Path) -> io::Result<(Self, Option<Vec<u8>>, Vec<Record>)> {
+        fs::create_dir_all(dir)?;
+        let (generation, contents) = match fs::read(dir.join(Self::SNAPSHOT)) {
+            Ok(bytes) => {
+                let (generation, contents) = parse_snapshot(&bytes).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "the snapshot is corrupt")
+                })?;
+                (generation, Some(contents.to_vec()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, None),
+            Err(e) => return Err(e),
+        };
+        let (log, records) = Log::open(&dir.join(Self::log_name(generation)))?;
+        let mut storage = Self {
+            dir: dir.to_path_buf(),
+            generation,
+            log,
+            flushed: None,
+            manifest: None,
+            pending: HashSet::new(),
+        };
+        storage.read_manifest(&records)?;
+        storage.remove_stale_logs()?;
+        Ok((storage, contents, records))
+    }
+
+    /// Read the manifest, if there is one for the current generation, and find the `Flush`
+    /// record it names in `records`
+    fn read_manifest(&mut self, records: &[Record]) -> io::Result<()> {
+        let bytes = match fs::read(self.dir.join(Self::MANIFEST)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let corrupt = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let (generation, flushed, contents) =
+            parse_manifest(&bytes).ok_or_else(|| corrupt("the index manifest is corrupt"))?;
+        if generation != self.generation {
+            return Ok(());
+        }
+        // A segment number may be named by more than one `Flush` record if the database crashed
+        // before writing the manifest for one, and the manifest always names the last
+        let covered = match flushed {
+            Some(segment) => {
+                records
+                    .iter()
+                    .rposition(|record| *record == Record::Flush { segment })
+                    .ok_or_else(|| corrupt("the index manifest does not match the log"))?
+                    + 1
+            }
+            None => 0,
+        };
+        self.flushed = flushed;
+        self.manifest = Some((contents.to_vec(), covered));
+        Ok(())
+    }
+
+    /// Take the contents of the manifest of the current generation, as written by the closure
+    /// passed to `flush` or `write_manifest`, along with the number of records at the start of
+    /// the log whose postings are in the segments it lists. Return `None` if there is no
+    /// manifest or it was already taken.
+    pub fn take_manifest(&mut self) -> Option<(Vec<u8>, usize)> {
+        self.manifest.take()
+    }
+
+    /// Append `record` to the log of the current generation
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        self.log.append(record)
+    }
+
+    /// Write a new snapshot whose contents are written by `write`, and start an empty log for
+    /// the changes made after it. Return the generation of the new snapshot.
+    pub fn snapshot<F>(&mut self, write: F) -> io::Result<u64>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+    {
+        let generation = self.generation + 1;
+        let temporary = self.dir.join(format!("{}.tmp", Self::SNAPSHOT));
+        {
+            let mut out = BufWriter::new(File::create(&temporary)?);
+            out.write_all(SNAPSHOT_MAGIC)?;
+            out.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+            out.write_all(&generation.to_be_bytes())?;
+            write(&mut out)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        fs::rename(&temporary, self.dir.join(Self::SNAPSHOT))?;
+        let (log, _) = Log::open(&self.dir.join(Self::log_name(generation)))?;
+        self.log = log;
+        self.generation = generation;
+        self.flushed = None;
+        self.remove_stale_logs()?;
+        // The manifest belongs to the previous generation, so it is no longer read
+        match fs::remove_file(self.dir.join(Self::MANIFEST)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        Ok(generation)
+    }
+
+    /// Record that the postings in memory were flushed to the segment stored under `segment`,
+    /// by appending a `Flush` record to the log and then writing a manifest whose contents are
+    /// written by `write`
+    pub fn flush<F>(&mut self, segment: u64, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+    {
+        self.append(&Record::Flush { segment })?;
+        self.write_manifest_as(Some(segment), write)?;
+        self.flushed = Some(segment);
+        Ok(())
+    }
+
+    /// Write a manifest whose contents are written by `write`, naming the same `Flush` record as
+    /// the last one, for when the segments change without a flush
+    pub fn write_manifest<F>(&mut self, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+    {
+        self.write_manifest_as(self.flushed, write)
+    }
+
+    fn write_manifest_as<F>(&self, flushed: Option<u64>, write: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+    {
+        let temporary = self.dir.join(format!("{}.tmp", Self::MANIFEST));
+        {
+            let mut out = BufWriter::new(File::create(&temporary)?);
+            let mut header = MANIFEST_MAGIC.to_vec();
+            write_u32(&mut header, MANIFEST_VERSION);
+            write_u64(&mut header, self.generation);
+            write_bool(&mut header, flushed.is_some());
+            write_u64(&mut header, flushed.unwrap_or(0));
+            out.write_all(&header)?;
+            write(&mut out)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        fs::rename(&temporary, self.dir.join(Self::MANIFEST))
+    }
+
+    /// The path of the segment of the reverse index stored under `number`
+    pub fn segment_path(&self, number: u64) -> PathBuf {
+        self.dir.join(format!("seg-{}.seg", number))
+    }
+
+    /// Note that the segment stored under `number` is being written, so that it isn't removed
+    /// by `remove_unused_segments` before the database starts using it, and return its path
+    pub fn reserve_segment(&mut self, number: u64) -> PathBuf {
+        self.pending.insert(number);
+        self.segment_path(number)
+    }
+
+    /// Note that the segment stored under `number` is no longer being written, either because
+    /// the database now uses it or because writing it failed
+    pub fn release_segment(&mut self, number: u64) {
+        self.pending.remove(&number);
+    }
+
+    /// Remove every segment except the ones stored under the numbers in `keep` and the ones being
+    /// written
+    pub fn remove_unused_segments(&self, keep: &[u64]) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let number = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("seg-")?.strip_suffix(".seg"))
+                .and_then(|number| number.parse::<u64>().ok());
+            if number
+                .is_some_and(|number| !keep.contains(&number) && !self.pending.contains(&number))
+            {
+                fs::remove_file(self.dir.join(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the logs of every generation but the current one
+    fn remove_stale_logs(&self) -> io::Result<()> {
+        let current = Self::log_name(self.generation);
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("wal-") && name.ends_with(".log") && name != current {
+                fs::remove_file(self.dir.join(name.as_ref()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a snapshot file into its generation and contents
+fn parse_snapshot(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    if bytes.get(..8)? != SNAPSHOT_MAGIC {
+        return None;
+    }
+    let version = u32::from_be_bytes(bytes.get(8..12)?.try_into().ok()?);
+    if version != SNAPSHOT_VERSION {
+        return None;
+    }
+    let generation = u64::from_be_bytes(bytes.get(12..20)?.try_into().ok()?);
+    Some((generation, &bytes[20..]))
+}
+
+/// Split a manifest file into its generation, the segment named by its `Flush` record and its
+/// contents
+fn parse_manifest(bytes: &[u8]) -> Option<(u64, Option<u64>, &[u8])> {
+    let mut reader = bytes.strip_prefix(MANIFEST_MAGIC)?;
+    if read_u32(&mut reader)? != MANIFEST_VERSION {
+        return None;
+    }
+    let generation = read_u64(&mut reader)?;
+    let has_flushed = read_bool(&mut reader)?;
+    let flushed = read_u64(&mut reader)?;
+    Some((generation, has_flushed.then_some(flushed), reader))
+}
+
+pub(crate) fn write_metadata(bytes: &mut Vec<u8>, metadata: &Option<Metadata>) {
+    write_bool(bytes, metadata.is_some());
+    if let Some(metadata) = metadata {
+        write_string(bytes, &metadata.title);
+        write_string(bytes, &metadata.author);
+        write_bool(bytes, metadata.year.is_some());
+        write_u32(bytes, metadata.year.unwrap_or(0));
+    }
+}
+
+pub(crate) fn read_metadata<R: Read>(reader: &mut R) -> Option<Option<Metadata>> {
+    if !read_bool(reader)? {
+        return Some(None);
+    }
+    let title = read_string(reader)?;
+    let author = read_string(reader)?;
+    let has_year = read_bool(reader)?;
+    let year = read_u32(reader)?;
+    Some(Some(Metadata {
+        title,
+        author,
+        year: has_year.then_some(year),
+    }))
+}
+
+/// Read the record framed at the start of `bytes`, along with the length of the frame
+fn read_frame(bytes: &[u8]) -> Option<(Record, usize)> {
+    let mut reader = bytes;
+    let len = read_u32(&mut reader)? as usize;
+    let hash = u64::from_be_bytes(reader.get(..8)?.try_into().ok()?);
+    let payload = reader.get(8..8 + len)?;
+    if hash_bytes(payload) != hash {
+        return None;
+    }
+    let record = Record::from_bytes(payload)?;
+    Some((record, 12 + len))
+}
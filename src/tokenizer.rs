@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+// A tokenizer turns the text of a document into the terms that are stored in the reverse index.
+// The database runs the same tokenizer over queries, so a query only matches what was indexed
+// if it is normalized the same way.
+
+/// A word of a text along with where it was found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    /// The normalized form of the word, as stored in the reverse index
+    pub term: String,
+    /// The byte range of the word in the original text
+    pub span: Range<usize>,
+}
+
+/// A way of splitting text into normalized terms
+pub trait Tokenizer: Send + Sync {
+    /// Split `text` into tokens, in the order they appear
+    fn tokens(&self, text: &str) -> Vec<Token>;
+
+    /// Split `text` into terms, in the order they appear
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self.tokens(text)
+            .into_iter()
+            .map(|token| token.term)
+            .collect()
+    }
+}
+
+/// Splits text on whitespace and keeps every word exactly as written
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, c) in text.char_indices() {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    tokens.push(token(text, s..i));
+                    start = None;
+                }
+                (false, None) => start = Some(i),
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            tokens.push(token(text, s..text.len()));
+        }
+        tokens
+    }
+}
+
+/// Splits text into words at Unicode word boundaries and then normalizes each word
+#[derive(Clone, Copy, Debug)]
+pub struct Pipeline {
+    /// Convert every word to lowercase
+    pub lowercase: bool,
+    /// Drop punctuation on its own and trim it from the ends of words
+    pub strip_punctuation: bool,
+    /// Drop words made up of digits and punctuation, like "1816" or "3:16"
+    pub remove_numerals: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            lowercase: true,
+            strip_punctuation: true,
+            remove_numerals: false,
+        }
+    }
+}
+
+impl Tokenizer for Pipeline {
+    fn tokens(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for (start, word) in text.split_word_bound_indices() {
+            let mut span = start..start + word.len();
+            if self.strip_punctuation {
+                let trimmed = word.trim_start_matches(is_punctuation);
+                span.start += word.len() - trimmed.len();
+                span.end = span.start + trimmed.trim_end_matches(is_punctuation).len();
+            }
+            let word = &text[span.clone()];
+            if word.trim().is_empty() {
+                continue;
+            }
+            if self.remove_numerals
+                && word.chars().any(char::is_numeric)
+                && !word.chars().any(char::is_alphabetic)
+            {
+                continue;
+            }
+            let term = if self.lowercase {
+                word.to_lowercase()
+            } else {
+                word.to_string()
+            };
+            tokens.push(Token { term, span });
+        }
+        tokens
+    }
+}
+
+/// The common English words that ship in `data/words.txt`, one per line
+pub const DEFAULT_STOP_WORDS: &str = include_str!("../data/words.txt");
+
+/// Parse a list of stop words separated by whitespace
+pub fn parse_stop_words(text: &str) -> HashSet<String> {
+    text.split_whitespace().map(str::to_string).collect()
+}
+
+/// The set of common English words that ship in `data/words.txt`
+pub fn default_stop_words() -> HashSet<String> {
+    parse_stop_words(DEFAULT_STOP_WORDS)
+}
+
+fn token(text: &str, span: Range<usize>) -> Token {
+    Token {
+        term: text[span.clone()].to_string(),
+        span,
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace()
+}
@@ -0,0 +1,67 @@
+// Posting lists are sorted, so they are stored as the gaps between consecutive values rather than
+// the values themselves, and each gap is written as a variable-length integer: seven bits to a
+// byte, lowest first, with the high bit set on every byte but the last. Gaps in a posting list
+// are usually small, so most take a single byte instead of eight.
+//
+// A sorted list is written as its length followed by the first value and then the gaps.
+
+use std::io::Read;
+
+/// Append `n` to `bytes` as a variable-length integer
+pub fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+/// Read a variable-length integer from `reader`, or return `None` if the input ends early or
+/// the integer doesn't fit in 64 bits
+pub fn read_varint<R: Read>(reader: &mut R) -> Option<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf).ok()?;
+        let byte = buf[0];
+        let bits = (byte & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            return None;
+        }
+        n |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// Append `values`, which must be in ascending order, to `bytes` as a sorted list
+pub fn write_sorted(bytes: &mut Vec<u8>, values: &[usize]) {
+    write_varint(bytes, values.len() as u64);
+    let mut previous = 0;
+    for &value in values {
+        debug_assert!(value >= previous, "values must be in ascending order");
+        write_varint(bytes, (value - previous) as u64);
+        previous = value;
+    }
+}
+
+/// Read a sorted list written by `write_sorted` from `reader`
+pub fn read_sorted<R: Read>(reader: &mut R) -> Option<Vec<usize>> {
+    let len = usize::try_from(read_varint(reader)?).ok()?;
+    // The length may be corrupt, so it only bounds the allocation up front
+    let mut values = Vec::with_capacity(len.min(1 << 16));
+    let mut value = 0usize;
+    for _ in 0..len {
+        value = value.checked_add(usize::try_from(read_varint(reader)?).ok()?)?;
+        values.push(value);
+    }
+    Some(values)
+}
+
+/// The length of the sorted list at the start of `bytes`, without decoding its values
+pub fn sorted_len(bytes: &[u8]) -> usize {
+    let mut bytes = bytes;
+    read_varint(&mut bytes).unwrap_or(0) as usize
+}
@@ -0,0 +1,170 @@
+use crate::fuzzy::BkTree;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::sync::RwLock;
+
+// The vocabulary keeps every word of the reverse index in sorted order, so that a pattern can be
+// expanded into the words it matches without hashing every possible word. Prefixes and globs
+// only look at the range of words that share their literal prefix, while regular expressions
+// have to check every word. The same words are kept in a BK-tree to find the ones close to a
+// misspelling. The n-grams of the reverse index are left out, as they are not spellings of words.
+
+/// How a pattern passed to `Vocabulary::expand` is interpreted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatternKind {
+    /// Terms starting with the pattern, like `whal` for "whale" and "whaling"
+    Prefix,
+    /// Terms matching the pattern, where `*` stands for any run of characters and `?` for any
+    /// one character, like `colo?r*` for "colour" and "colored"
+    Glob,
+    /// Terms matched in full by the regular expression, like `colou?r`
+    Regex,
+}
+
+/// A sorted, thread-safe set of terms
+pub struct Vocabulary {
+    terms: RwLock<Terms>,
+}
+
+struct Terms {
+    sorted: BTreeSet<String>,
+    tree: BkTree,
+}
+
+impl Default for Vocabulary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vocabulary {
+    /// Create a new empty vocabulary
+    pub fn new() -> Self {
+        Self {
+            terms: RwLock::new(Terms {
+                sorted: BTreeSet::new(),
+                tree: BkTree::new(),
+            }),
+        }
+    }
+
+    /// Add every term in `terms` to the vocabulary
+    pub fn extend<I: IntoIterator<Item = String>>(&self, terms: I) {
+        let mut guard = self.terms.write().unwrap();
+        let Terms { sorted, tree } = &mut *guard;
+        for term in terms {
+            if !sorted.contains(&term) {
+                tree.insert(term.clone());
+                sorted.insert(term);
+            }
+        }
+    }
+
+    /// Remove `term` from the vocabulary, if it is there
+    pub fn remove(&self, term: &str) {
+        let mut guard = self.terms.write().unwrap();
+        if guard.sorted.remove(term) {
+            guard.tree.remove(term);
+        }
+    }
+
+    /// The number of terms in the vocabulary
+    pub fn len(&self) -> usize {
+        self.terms.read().unwrap().sorted.len()
+    }
+
+    /// Whether the vocabulary has no terms
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get every term, in sorted order
+    pub fn terms(&self) -> Vec<String> {
+        self.terms.read().unwrap().sorted.iter().cloned().collect()
+    }
+
+    /// Get the terms starting with `prefix`, in sorted order, skipping the first `offset` and
+    /// returning at most `limit`, along with the number of terms starting with `prefix`
+    pub fn page(&self, prefix: &str, offset: usize, limit: usize) -> (Vec<String>, usize) {
+        let guard = self.terms.read().unwrap();
+        let page = with_prefix(&guard.sorted, prefix)
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        (page, with_prefix(&guard.sorted, prefix).count())
+    }
+
+    /// Get every term matching `pattern`, in sorted order. Fails only if `kind` is
+    /// `PatternKind::Regex` and `pattern` is not a valid regular expression.
+    pub fn expand(&self, kind: PatternKind, pattern: &str) -> Result<Vec<String>, regex::Error> {
+        let guard = self.terms.read().unwrap();
+        let terms = &guard.sorted;
+        let matches = match kind {
+            PatternKind::Prefix => with_prefix(terms, pattern).cloned().collect(),
+            PatternKind::Glob => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                let literal: String = pattern.iter().take_while(|c| !is_wildcard(c)).collect();
+                with_prefix(terms, &literal)
+                    .filter(|term| glob_matches(&pattern, &term.chars().collect::<Vec<_>>()))
+                    .cloned()
+                    .collect()
+            }
+            PatternKind::Regex => {
+                let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+                terms
+                    .iter()
+                    .filter(|term| regex.is_match(term))
+                    .cloned()
+                    .collect()
+            }
+        };
+        Ok(matches)
+    }
+
+    /// Get every term within `max_distance` single character edits of `word`, paired with its
+    /// distance, closest first and then in sorted order
+    pub fn fuzzy(&self, word: &str, max_distance: usize) -> Vec<(String, usize)> {
+        self.terms.read().unwrap().tree.search(word, max_distance)
+    }
+}
+
+fn with_prefix<'a>(
+    terms: &'a BTreeSet<String>,
+    prefix: &'a str,
+) -> impl Iterator<Item = &'a String> + 'a {
+    terms
+        .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(move |term| term.starts_with(prefix))
+}
+
+fn is_wildcard(c: &char) -> bool {
+    *c == '*' || *c == '?'
+}
+
+/// Whether `text` matches the glob `pattern` in full
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    // `matched[j]` is whether the pattern so far matches the first `j` characters of `text`
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    for &p in pattern {
+        let mut next = vec![false; text.len() + 1];
+        match p {
+            '*' => {
+                let mut any = false;
+                for j in 0..=text.len() {
+                    any |= matched[j];
+                    next[j] = any;
+                }
+            }
+            p => {
+                for j in 1..=text.len() {
+                    next[j] = matched[j - 1] && (p == '?' || p == text[j - 1]);
+                }
+            }
+        }
+        matched = next;
+    }
+    matched[text.len()]
+}
@@ -1,3 +1,9 @@
+#![allow(
+    clippy::unnecessary_cast,
+    clippy::assertions_on_constants,
+    clippy::clone_on_copy,
+    clippy::empty_loop
+)]
 use quickcheck::quickcheck;
 const THREADS: usize = 16;
 
@@ -69,6 +75,34 @@ mod test_multimap {
         quickcheck(no_duplicates as fn(i32, usize));
     }
     #[test]
+    fn test_update_5() {
+        let map = ConcurrentMultiMap::<UnCloneable, usize>::new(10);
+        map.update(UnCloneable(1), |v| *v = Some(10));
+        map.update(UnCloneable(2), |v| *v = Some(20));
+        map.update(UnCloneable(1), |v| *v = v.map(|v| v + 1));
+        assert_eq!(map.get(&UnCloneable(1)), vec![11]);
+        map.update(UnCloneable(1), |v| *v = None);
+        assert_eq!(map.get(&UnCloneable(1)), Vec::<usize>::new());
+        assert_eq!(map.get(&UnCloneable(2)), vec![20]);
+    }
+    #[test]
+    fn test_for_each_visits_every_pair_5() {
+        fn for_each_visits_every_pair(tuples: Vec<(i32, usize)>) {
+            let map = ConcurrentMultiMap::<UnCloneable, usize>::new(10);
+            for (k, v) in tuples.iter() {
+                map.set(UnCloneable(*k), *v);
+            }
+            let mut visited = Vec::new();
+            map.for_each(|k, v| visited.push((k.0, *v)));
+            let mut expected = tuples.clone();
+            expected.sort();
+            expected.dedup();
+            visited.sort();
+            assert_eq!(visited, expected);
+        }
+        quickcheck(for_each_visits_every_pair as fn(Vec<(i32, usize)>));
+    }
+    #[test]
     fn passes_stress_test_10() {
         fn passes_stress_test(tuples: Vec<(i32, usize, bool)>) {
             use std::sync::Arc;
@@ -171,7 +205,7 @@ mod test_serialize {
     #[test]
     fn test_round_trip_response_5() {
         fn round_trip_response(s: String, n: usize) {
-            let pub_response = Response::PublishSuccess(n);
+            let pub_response = Response::PublishSuccess(n, vec![(n, 0.5)]);
             let search_response = Response::SearchSuccess(vec![n]);
             let retrieve_response = Response::RetrieveSuccess(s.clone());
             assert_eq!(
@@ -189,6 +223,1879 @@ mod test_serialize {
         }
         quickcheck(round_trip_response as fn(String, usize));
     }
+
+    #[test]
+    fn test_round_trip_search_ngram_5() {
+        fn round_trip_search_ngram(s: String) {
+            let request = Request::SearchNgram { ngram: s };
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+        }
+        quickcheck(round_trip_search_ngram as fn(String));
+    }
+
+    #[test]
+    fn test_round_trip_count_5() {
+        fn round_trip_count(s: String, counts: Vec<(usize, usize)>) {
+            let request = Request::Count { ngram: s };
+            let response = Response::CountSuccess(counts);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_count as fn(String, Vec<(usize, usize)>));
+    }
+
+    #[test]
+    fn test_round_trip_timeline_5() {
+        fn round_trip_timeline(s: String, year: u32, normalize: bool, points: Vec<(u32, u32)>) {
+            let publish = Request::PublishWithMetadata {
+                doc: s.clone(),
+                title: s.clone(),
+                author: s.clone(),
+                year,
+            };
+            let request = Request::Timeline {
+                ngram: s,
+                normalize,
+                smoothing: year,
+            };
+            let points = points
+                .into_iter()
+                .map(|(year, n)| (year, n as f64 / 7.0))
+                .collect();
+            let response = Response::TimelineSuccess(points);
+            assert_eq!(
+                Request::from_bytes(&publish.to_bytes()[..]).unwrap(),
+                publish
+            );
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_timeline as fn(String, u32, bool, Vec<(u32, u32)>));
+    }
+
+    #[test]
+    fn test_round_trip_query_5() {
+        fn round_trip_query(s: String) {
+            let request = Request::Query { query: s.clone() };
+            let response = Response::Error(s);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_query as fn(String));
+    }
+
+    #[test]
+    fn test_round_trip_positions_5() {
+        fn round_trip_positions(s: String, n: usize, matches: Vec<(usize, Vec<usize>)>) {
+            let phrase = Request::Phrase { phrase: s.clone() };
+            let near = Request::Near {
+                left: s.clone(),
+                right: s,
+                distance: n,
+            };
+            let response = Response::PositionsSuccess(matches);
+            assert_eq!(Request::from_bytes(&phrase.to_bytes()[..]).unwrap(), phrase);
+            assert_eq!(Request::from_bytes(&near.to_bytes()[..]).unwrap(), near);
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_positions as fn(String, usize, Vec<(usize, Vec<usize>)>));
+    }
+
+    #[test]
+    fn test_round_trip_pattern_5() {
+        use ngram::vocabulary::PatternKind;
+        fn round_trip_pattern(s: String, terms: Vec<String>, ids: Vec<usize>) {
+            for kind in [PatternKind::Prefix, PatternKind::Glob, PatternKind::Regex] {
+                let request = Request::Pattern {
+                    pattern: s.clone(),
+                    kind,
+                };
+                assert_eq!(
+                    Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                    request
+                );
+            }
+            let response = Response::PatternSuccess(terms, ids);
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_pattern as fn(String, Vec<String>, Vec<usize>));
+    }
+
+    #[test]
+    fn test_round_trip_fuzzy_5() {
+        fn round_trip_fuzzy(s: String, n: usize, variants: Vec<(String, Vec<usize>)>) {
+            let request = Request::FuzzySearch {
+                word: s,
+                max_distance: n,
+            };
+            let response = Response::FuzzySuccess(variants);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_fuzzy as fn(String, usize, Vec<(String, Vec<usize>)>));
+    }
+
+    #[test]
+    fn test_round_trip_ranked_5() {
+        fn round_trip_ranked(s: String, k: usize, bm25: bool, scores: Vec<(usize, i32)>) {
+            let scoring = if bm25 {
+                ngram::database::Scoring::Bm25 {
+                    k1: k as f64 / 3.0,
+                    b: 0.75,
+                }
+            } else {
+                ngram::database::Scoring::TfIdf
+            };
+            let request = Request::RankedSearch {
+                query: s,
+                scoring,
+                k,
+            };
+            let scores = scores
+                .into_iter()
+                .map(|(id, n)| (id, n as f64 / 7.0))
+                .collect();
+            let response = Response::RankedSuccess(scores);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_ranked as fn(String, usize, bool, Vec<(usize, i32)>));
+    }
+
+    #[test]
+    fn test_round_trip_concordance_5() {
+        fn round_trip_concordance(s: String, window: usize, hits: Vec<(usize, String, String)>) {
+            let request = Request::Concordance {
+                term: s.clone(),
+                window,
+                limit: hits.len(),
+            };
+            let lines = hits
+                .into_iter()
+                .map(|(id, left, right)| ngram::database::ConcordanceLine {
+                    id,
+                    offset: window,
+                    left,
+                    keyword: s.clone(),
+                    right,
+                })
+                .collect();
+            let response = Response::ConcordanceSuccess(lines);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_concordance as fn(String, usize, Vec<(usize, String, String)>));
+    }
+
+    #[test]
+    fn test_round_trip_snippets_5() {
+        fn round_trip_snippets(s: String, k: usize, hits: Vec<(usize, String, Vec<usize>)>) {
+            let request = Request::SnippetSearch {
+                query: s,
+                scoring: ngram::database::Scoring::TfIdf,
+                k,
+                words: k / 2,
+            };
+            let snippets = hits
+                .into_iter()
+                .map(|(id, text, starts)| ngram::database::Snippet {
+                    id,
+                    score: id as f64 / 7.0,
+                    text,
+                    highlights: starts.into_iter().map(|s| s..s / 2 + 1).collect(),
+                })
+                .collect();
+            let response = Response::SnippetSuccess(snippets);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_snippets as fn(String, usize, Vec<(usize, String, Vec<usize>)>));
+    }
+
+    #[test]
+    fn test_round_trip_collocations_5() {
+        fn round_trip_collocations(s: String, window: usize, pmi: bool, words: Vec<(String, u8)>) {
+            let request = Request::Collocations {
+                term: s,
+                window,
+                measure: if pmi {
+                    ngram::database::Association::Pmi
+                } else {
+                    ngram::database::Association::LogLikelihood
+                },
+                min_count: window / 3,
+                k: words.len(),
+            };
+            let collocations = words
+                .into_iter()
+                .map(|(word, count)| ngram::database::Collocation {
+                    word,
+                    count: count as usize,
+                    pmi: count as f64 / 3.0,
+                    log_likelihood: count as f64 * 1.5,
+                })
+                .collect();
+            let response = Response::CollocationSuccess(collocations);
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_collocations as fn(String, usize, bool, Vec<(String, u8)>));
+    }
+
+    #[test]
+    fn test_round_trip_stats_5() {
+        fn round_trip_stats(id: usize, tokens: usize, top_terms: Vec<(String, usize)>) {
+            let requests = vec![
+                Request::Stats { top_k: id },
+                Request::DocStats { id, top_k: tokens },
+            ];
+            for request in requests {
+                assert_eq!(
+                    Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                    request
+                );
+            }
+            let responses = vec![
+                Response::StatsSuccess(ngram::database::CorpusStats {
+                    documents: id,
+                    tokens,
+                    vocabulary: top_terms.len(),
+                    average_length: tokens as f64 / 7.0,
+                    top_terms: top_terms.clone(),
+                }),
+                Response::DocStatsSuccess(ngram::database::DocStats {
+                    id,
+                    tokens,
+                    distinct: top_terms.len(),
+                    top_terms,
+                }),
+            ];
+            for response in responses {
+                assert_eq!(
+                    Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                    response
+                );
+            }
+        }
+        quickcheck(round_trip_stats as fn(usize, usize, Vec<(String, usize)>));
+    }
+
+    #[test]
+    fn test_round_trip_terms_5() {
+        fn round_trip_terms(prefix: String, offset: usize, terms: Vec<(String, usize, usize)>) {
+            let request = Request::ListTerms {
+                prefix,
+                offset,
+                limit: terms.len(),
+            };
+            let response = Response::TermsSuccess(
+                terms
+                    .into_iter()
+                    .map(
+                        |(term, documents, occurrences)| ngram::database::TermFrequency {
+                            term,
+                            documents,
+                            occurrences,
+                        },
+                    )
+                    .collect(),
+                offset,
+            );
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_terms as fn(String, usize, Vec<(String, usize, usize)>));
+    }
+
+    #[test]
+    fn test_round_trip_delete_5() {
+        fn round_trip_delete(id: usize) {
+            let request = Request::Delete { id };
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            for response in [
+                Response::DeleteSuccess(id),
+                Response::Deleted(id),
+                Response::ReplaceSuccess(id),
+                Response::Duplicate(id),
+            ] {
+                assert_eq!(
+                    Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                    response
+                );
+            }
+        }
+        quickcheck(round_trip_delete as fn(usize));
+    }
+
+    #[test]
+    fn test_round_trip_replace_5() {
+        fn round_trip_replace(id: usize, doc: String) {
+            let request = Request::Replace { id, doc };
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+        }
+        quickcheck(round_trip_replace as fn(usize, String));
+    }
+
+    #[test]
+    fn test_round_trip_varint_5() {
+        use ngram::varint::*;
+        fn round_trip_varint(n: u64, mut values: Vec<usize>) {
+            values.sort();
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, n);
+            write_sorted(&mut bytes, &values);
+            let mut reader = &bytes[..];
+            assert_eq!(read_varint(&mut reader), Some(n));
+            assert_eq!(sorted_len(reader), values.len());
+            assert_eq!(read_sorted(&mut reader), Some(values));
+            assert!(reader.is_empty());
+        }
+        quickcheck(round_trip_varint as fn(u64, Vec<usize>));
+
+        let mut bytes = Vec::new();
+        write_sorted(&mut bytes, &[3, 130, 131]);
+        assert_eq!(bytes, vec![3, 3, 127, 1]);
+        assert_eq!(read_varint(&mut &[0x80, 0x80][..]), None);
+        assert_eq!(read_varint(&mut &[0xff; 10][..]), None);
+    }
+
+    #[test]
+    fn test_round_trip_lz_5() {
+        use ngram::lz::*;
+        fn round_trip_lz(data: Vec<u8>, repeats: usize) {
+            let data = data.repeat(repeats % 8 + 1);
+            assert_eq!(decompress(&compress(&data)), Some(data));
+        }
+        quickcheck(round_trip_lz as fn(Vec<u8>, usize));
+
+        let text = "Call me Ishmael. Some years ago, never mind how long precisely. ".repeat(100);
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() * 10 < text.len());
+        assert_eq!(decompress(&compressed[..compressed.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_round_trip_compressed_search_5() {
+        fn round_trip_compressed_search(word: String, mut ids: Vec<usize>) {
+            let request = Request::CompressedSearch { word };
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            ids.sort();
+            let compressed = Response::CompressedSearchSuccess(ids.clone()).to_bytes();
+            assert_eq!(
+                Response::from_bytes(&compressed[..]).unwrap(),
+                Response::CompressedSearchSuccess(ids)
+            );
+        }
+        quickcheck(round_trip_compressed_search as fn(String, Vec<usize>));
+    }
+
+    #[test]
+    fn test_round_trip_snapshot_5() {
+        fn round_trip_snapshot(generation: u64) {
+            let request = Request::Snapshot;
+            assert_eq!(
+                Request::from_bytes(&request.to_bytes()[..]).unwrap(),
+                request
+            );
+            let response = Response::SnapshotSuccess(generation);
+            assert_eq!(
+                Response::from_bytes(&response.to_bytes()[..]).unwrap(),
+                response
+            );
+        }
+        quickcheck(round_trip_snapshot as fn(u64));
+    }
+
+    #[test]
+    fn test_truncated_request_5() {
+        let bytes = Request::Publish {
+            doc: "call me ishmael".to_string(),
+        }
+        .to_bytes();
+        assert_eq!(Request::from_bytes(&bytes[..bytes.len() - 1]), None);
+    }
+}
+
+// ============================ TOKENIZER ============================
+mod test_tokenizer {
+    use ngram::tokenizer::*;
+
+    #[test]
+    fn test_whitespace_spans_5() {
+        let text = " Call me\tIshmael. ";
+        let tokens = WhitespaceTokenizer.tokens(text);
+        let terms: Vec<&str> = tokens.iter().map(|t| t.term.as_str()).collect();
+        assert_eq!(terms, vec!["Call", "me", "Ishmael."]);
+        for token in tokens {
+            assert_eq!(&text[token.span], token.term);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_normalizes_5() {
+        let text = "\"Emma,\" said Mr. Knightley -- in 1816, don't you know?";
+        assert_eq!(
+            Pipeline::default().tokenize(text),
+            vec![
+                "emma",
+                "said",
+                "mr",
+                "knightley",
+                "in",
+                "1816",
+                "don't",
+                "you",
+                "know"
+            ]
+        );
+        let pipeline = Pipeline {
+            remove_numerals: true,
+            ..Pipeline::default()
+        };
+        let tokens = pipeline.tokens(text);
+        assert_eq!(tokens.len(), 8);
+        assert_eq!(&text[tokens[0].span.clone()], "Emma");
+        assert_eq!(&text[tokens[3].span.clone()], "Knightley");
+    }
+
+    #[test]
+    fn test_pipeline_keeps_punctuation_5() {
+        let pipeline = Pipeline {
+            lowercase: false,
+            strip_punctuation: false,
+            remove_numerals: false,
+        };
+        assert_eq!(
+            pipeline.tokenize("Emma, dear."),
+            vec!["Emma", ",", "dear", "."]
+        );
+    }
+}
+
+// ============================ QUERY ============================
+mod test_query {
+    use ngram::query::*;
+
+    fn term(t: &str) -> Box<Query> {
+        Box::new(Query::Term(t.to_string()))
+    }
+
+    #[test]
+    fn test_parse_precedence_5() {
+        let query = Query::parse("whale AND (ship OR boat) NOT ahab").unwrap();
+        let expected = Query::And(
+            Box::new(Query::And(
+                term("whale"),
+                Box::new(Query::Or(term("ship"), term("boat"))),
+            )),
+            Box::new(Query::Not(term("ahab"))),
+        );
+        assert_eq!(query, expected);
+        assert_eq!(
+            Query::parse("a OR b c").unwrap(),
+            Query::Or(term("a"), Box::new(Query::And(term("b"), term("c"))))
+        );
+        assert_eq!(
+            Query::parse("and or").unwrap(),
+            Query::And(term("and"), term("or"))
+        );
+    }
+
+    #[test]
+    fn test_parse_phrase_near_5() {
+        assert_eq!(
+            Query::parse("\"to be or not to be\" NOT (whale NEAR/3 \"white shark\")").unwrap(),
+            Query::And(
+                Box::new(Query::Phrase("to be or not to be".to_string())),
+                Box::new(Query::Not(Box::new(Query::Near {
+                    left: "whale".to_string(),
+                    right: "white shark".to_string(),
+                    distance: 3,
+                })))
+            )
+        );
+        assert_eq!(Query::parse("\"to be"), Err(ParseError::UnclosedQuote));
+        assert_eq!(Query::parse("whale NEAR/2"), Err(ParseError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_parse_errors_5() {
+        assert_eq!(Query::parse("  "), Err(ParseError::Empty));
+        assert_eq!(Query::parse("whale AND"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            Query::parse("(whale OR ship"),
+            Err(ParseError::UnclosedParenthesis)
+        );
+        assert_eq!(
+            Query::parse("whale)"),
+            Err(ParseError::Unexpected(")".to_string()))
+        );
+        assert_eq!(
+            Query::parse("OR whale"),
+            Err(ParseError::Unexpected("OR".to_string()))
+        );
+    }
+}
+
+// ============================ FUZZY ============================
+mod test_fuzzy {
+    use super::*;
+    use ngram::fuzzy::*;
+
+    #[test]
+    fn test_levenshtein_5() {
+        assert_eq!(levenshtein("shew", "show"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("flaw", "lawn"), 2);
+    }
+
+    #[test]
+    fn test_bk_tree_matches_scan_5() {
+        fn matches_scan(terms: Vec<String>, query: String, max_distance: u8) {
+            let max_distance = (max_distance % 4) as usize;
+            let mut tree = BkTree::new();
+            for term in terms.iter() {
+                tree.insert(term.clone());
+            }
+            let mut expected: Vec<(String, usize)> = terms
+                .iter()
+                .map(|t| (t.clone(), levenshtein(t, &query)))
+                .filter(|(_, d)| *d <= max_distance)
+                .collect();
+            expected.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+            expected.dedup();
+            assert_eq!(tree.search(&query, max_distance), expected);
+        }
+        quickcheck(matches_scan as fn(Vec<String>, String, u8));
+    }
+}
+
+// ============================ DEDUP ============================
+mod test_dedup {
+    use ngram::dedup::*;
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_content_hash_5() {
+        assert_eq!(content_hash(""), 0xcbf29ce484222325);
+        assert_eq!(content_hash("a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(
+            content_hash("call me ishmael"),
+            content_hash("call me ishmael.")
+        );
+    }
+
+    #[test]
+    fn test_similarity_5() {
+        let text = "it was the best of times it was the worst of times it was the age of \
+                    wisdom it was the age of foolishness it was the epoch of belief";
+        let original = signature(&words(text));
+        assert_eq!(original.len(), PERMUTATIONS);
+        assert_eq!(similarity(&original, &signature(&words(text))), 1.0);
+
+        let edited = signature(&words(&text.replace("foolishness", "folly")));
+        let similar = similarity(&original, &edited);
+        assert!(similar > 0.6 && similar < 1.0);
+
+        let other = signature(&words("call me ishmael some years ago never mind how long"));
+        assert!(similarity(&original, &other) < 0.2);
+        assert_eq!(similarity(&signature(&[]), &signature(&[])), 0.0);
+    }
+}
+
+// ============================ STORAGE ============================
+mod test_storage {
+    use super::*;
+    use ngram::database::Metadata;
+    use ngram::storage::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    /// A fresh directory for a test to keep its files in
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ngram-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_round_trip_record_5() {
+        fn round_trip_record(id: usize, doc: String, title: Option<String>, year: Option<u32>) {
+            let records = vec![
+                Record::Publish {
+                    id,
+                    doc: doc.clone(),
+                    metadata: title.map(|title| Metadata {
+                        title,
+                        author: doc.clone(),
+                        year,
+                    }),
+                },
+                Record::Delete { id },
+                Record::Replace { id, doc },
+                Record::Flush { segment: id as u64 },
+            ];
+            for record in records {
+                assert_eq!(Record::from_bytes(&record.to_bytes()[..]).unwrap(), record);
+            }
+        }
+        quickcheck(round_trip_record as fn(usize, String, Option<String>, Option<u32>));
+    }
+
+    #[test]
+    fn test_log_drops_torn_record_5() {
+        let dir = scratch("torn");
+        let path = dir.join(Storage::log_name(0));
+        let records = vec![
+            Record::Publish {
+                id: 0,
+                doc: "call me ishmael".to_string(),
+                metadata: None,
+            },
+            Record::Delete { id: 0 },
+        ];
+        {
+            let (mut log, existing) = Log::open(&path).unwrap();
+            assert_eq!(existing, Vec::new());
+            for record in &records {
+                log.append(record).unwrap();
+            }
+        }
+        let good = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0, 0, 0, 9, 1, 2, 3])
+            .unwrap();
+
+        let (mut log, existing) = Log::open(&path).unwrap();
+        assert_eq!(existing, records);
+        assert_eq!(fs::metadata(&path).unwrap().len(), good);
+        log.append(&Record::Delete { id: 1 }).unwrap();
+        drop(log);
+        let (_, existing) = Log::open(&path).unwrap();
+        assert_eq!(existing.len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_database_survives_restart_5() {
+        use ngram::database::Database;
+        let dir = scratch("restart");
+        let (id0, id1, id2) = {
+            let db = Database::new().with_storage(&dir).unwrap();
+            let id0 = db
+                .publish("[Moby Dick by Herman Melville 1851]\nthe white whale".to_string())
+                .unwrap();
+            let id1 = db
+                .publish_with_metadata(
+                    "the whaling ship".to_string(),
+                    Metadata {
+                        title: "Typee".to_string(),
+                        author: "Herman Melville".to_string(),
+                        year: Some(1846),
+                    },
+                )
+                .unwrap();
+            let id2 = db.publish("a sea voyage".to_string()).unwrap();
+            assert!(db.delete(id0).unwrap());
+            assert!(db.replace(id2, "a long sea voyage".to_string()).unwrap());
+            (id0, id1, id2)
+        };
+
+        let db = Database::new().with_storage(&dir).unwrap();
+        assert!(db.is_deleted(id0));
+        assert_eq!(db.search("whale"), Vec::<usize>::new());
+        assert_eq!(db.search("ship"), vec![id1]);
+        assert_eq!(db.metadata(id1).unwrap().year, Some(1846));
+        assert_eq!(db.retrieve(id2), Some("a long sea voyage".to_string()));
+        assert_eq!(db.year_totals(), vec![(1846, 3), (1851, 0)]);
+        assert_eq!(db.publish("another book".to_string()).unwrap(), 3);
+        drop(db);
+        let db = Database::new().with_storage(&dir).unwrap();
+        assert_eq!(db.search("another"), vec![3]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_then_log_5() {
+        use ngram::database::Database;
+        use ngram::vocabulary::PatternKind;
+        let dir = scratch("snapshot");
+        {
+            let db = Database::new()
+                .with_max_ngram(2)
+                .with_storage(&dir)
+                .unwrap();
+            db.publish("[Moby Dick by Herman Melville 1851]\nthe white whale".to_string())
+                .unwrap();
+            db.publish("the whaling ship".to_string()).unwrap();
+            db.publish("a sea voyage".to_string()).unwrap();
+            assert!(db.delete(1).unwrap());
+            assert_eq!(db.snapshot().unwrap(), 1);
+            assert!(db.replace(2, "a long sea voyage".to_string()).unwrap());
+            db.publish("the white ship".to_string()).unwrap();
+        }
+        assert!(dir.join(Storage::SNAPSHOT).exists());
+        assert!(!dir.join(Storage::log_name(0)).exists());
+
+        let db = Database::new()
+            .with_max_ngram(2)
+            .with_storage(&dir)
+            .unwrap();
+        assert!(db.is_deleted(1));
+        assert_eq!(db.search_ngram("white whale"), vec![0]);
+        assert_eq!(db.search("white"), vec![0, 3]);
+        assert_eq!(db.search("ship"), vec![3]);
+        assert_eq!(db.retrieve(2), Some("a long sea voyage".to_string()));
+        assert_eq!(db.year_totals(), vec![(1851, 3)]);
+        assert_eq!(
+            db.fuzzy_search("whales", 1),
+            vec![("whale".to_string(), vec![0])]
+        );
+        assert_eq!(
+            db.expand(PatternKind::Prefix, "white").unwrap(),
+            vec!["white"]
+        );
+        assert_eq!(db.snapshot().unwrap(), 2);
+        drop(db);
+
+        // A snapshot indexed with other options is indexed again
+        let db = Database::new().with_storage(&dir).unwrap();
+        assert_eq!(
+            db.expand(PatternKind::Prefix, "white").unwrap(),
+            vec!["white"]
+        );
+        assert_eq!(db.search_ngram("white whale"), vec![0]);
+        assert!(db.is_deleted(1));
+        assert_eq!(db.publish("another book".to_string()).unwrap(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segment_lookup_5() {
+        use ngram::database::Posting;
+        use ngram::segment::*;
+        use std::collections::HashSet;
+        let dir = scratch("segment");
+        let posting = |id: usize, positions: &[usize]| Posting {
+            id,
+            positions: positions.to_vec(),
+        };
+        let first = dir.join("first.seg");
+        let second = dir.join("second.seg");
+        Segment::write(
+            &first,
+            vec![
+                (
+                    "sea".to_string(),
+                    vec![posting(0, &[3]), posting(2, &[0, 5])],
+                ),
+                ("whale".to_string(), vec![posting(0, &[1])]),
+                ("whaling".to_string(), vec![posting(1, &[0])]),
+            ],
+        )
+        .unwrap();
+        Segment::write(&second, vec![("whale".to_string(), vec![posting(3, &[2])])]).unwrap();
+
+        let a = Segment::open(&first).unwrap();
+        let b = Segment::open(&second).unwrap();
+        assert_eq!(a.len(), 3);
+        assert_eq!(a.get("sea"), vec![posting(0, &[3]), posting(2, &[0, 5])]);
+        assert_eq!(a.get("ship"), Vec::new());
+        let keys: Vec<String> = a.with_prefix("wha").map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["whale", "whaling"]);
+
+        let masked = HashSet::from([0]);
+        let none = HashSet::new();
+        let merged: Vec<_> = merge(&[(&a, &masked), (&b, &none)]).collect();
+        assert_eq!(
+            merged,
+            vec![
+                ("sea".to_string(), vec![posting(2, &[0, 5])]),
+                ("whale".to_string(), vec![posting(3, &[2])]),
+                ("whaling".to_string(), vec![posting(1, &[0])]),
+            ]
+        );
+
+        fs::write(&second, b"NGRAMSEG").unwrap();
+        assert!(Segment::open(&second).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pending_segment_kept_5() {
+        let dir = scratch("pending");
+        let (mut storage, _, _) = Storage::open(&dir).unwrap();
+        fs::write(storage.segment_path(0), b"in use").unwrap();
+        let pending = storage.reserve_segment(1);
+        fs::write(&pending, b"being merged").unwrap();
+        fs::write(storage.segment_path(2), b"unused").unwrap();
+
+        storage.remove_unused_segments(&[0]).unwrap();
+        assert!(storage.segment_path(0).exists());
+        assert!(pending.exists());
+        assert!(!storage.segment_path(2).exists());
+
+        storage.release_segment(1);
+        storage.remove_unused_segments(&[0]).unwrap();
+        assert!(!pending.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replace_flushed_while_searching_5() {
+        use ngram::database::Database;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        let dir = scratch("replace-flushed");
+        let db = Arc::new(Database::new().with_storage(&dir).unwrap());
+        let id = db.publish("the white whale".to_string()).unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let searcher = {
+            let (db, done) = (Arc::clone(&db), Arc::clone(&done));
+            std::thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    assert_eq!(db.search("whale"), vec![id]);
+                }
+            })
+        };
+        for i in 0..50 {
+            db.snapshot().unwrap();
+            let doc = format!("the white whale number {}", i);
+            assert!(db.replace(id, doc).unwrap());
+        }
+        done.store(true, Ordering::SeqCst);
+        searcher.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush_without_snapshot_5() {
+        use ngram::database::Database;
+        let dir = scratch("flush");
+        let open = || {
+            Database::new()
+                .with_flush_threshold(4)
+                .with_storage(&dir)
+                .unwrap()
+        };
+        let db = open();
+        db.publish("the white whale".to_string()).unwrap();
+        db.publish("a sea voyage".to_string()).unwrap();
+        assert!(db.delete(0).unwrap());
+        assert!(db.replace(1, "the sea".to_string()).unwrap());
+        db.publish("call me ishmael".to_string()).unwrap();
+        assert_eq!(db.segment_count(), 2);
+        assert!(dir.join(Storage::MANIFEST).exists());
+        assert!(!dir.join(Storage::SNAPSHOT).exists());
+        db.publish("a whale ship".to_string()).unwrap();
+        drop(db);
+
+        let check = |db: &Database| {
+            assert!(db.is_deleted(0));
+            assert_eq!(db.retrieve(1).as_deref(), Some("the sea"));
+            assert_eq!(db.search("sea"), vec![1]);
+            assert_eq!(db.search("voyage"), Vec::<usize>::new());
+            assert_eq!(db.search("whale"), vec![3]);
+            assert_eq!(db.search("ishmael"), vec![2]);
+            let (terms, _) = db.list_terms("", 0, 100);
+            assert!(terms.iter().all(|term| term.documents == 1));
+        };
+        let db = open();
+        check(&db);
+        drop(db);
+
+        // Without the manifest, the flushed segments are ignored and the whole log is indexed
+        // again
+        fs::remove_file(dir.join(Storage::MANIFEST)).unwrap();
+        let db = open();
+        assert_eq!(db.segment_count(), 0);
+        check(&db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush_and_merge_segments_5() {
+        use ngram::database::{Database, MAX_SEGMENTS};
+        let dir = scratch("segments");
+        let open = || {
+            Database::new()
+                .with_flush_threshold(1)
+                .with_storage(&dir)
+                .unwrap()
+        };
+        let db = open();
+        for i in 0..=MAX_SEGMENTS {
+            db.publish(format!("the white whale number{}", i)).unwrap();
+        }
+        assert_eq!(db.segment_count(), MAX_SEGMENTS + 1);
+        assert_eq!(db.search("whale").len(), MAX_SEGMENTS + 1);
+
+        assert!(db.delete(0).unwrap());
+        assert!(db.replace(1, "a sea voyage".to_string()).unwrap());
+        assert_eq!(db.search("number0"), Vec::<usize>::new());
+        assert_eq!(db.search("sea"), vec![1]);
+        assert_eq!(db.search("whale").len(), MAX_SEGMENTS - 1);
+        assert_eq!(
+            db.expand(ngram::vocabulary::PatternKind::Prefix, "number1")
+                .unwrap(),
+            Vec::<String>::new()
+        );
+
+        assert!(db.merge_segments().unwrap());
+        assert!(!db.merge_segments().unwrap());
+        assert!(db.segment_count() < MAX_SEGMENTS);
+        assert_eq!(db.search("sea"), vec![1]);
+        assert_eq!(db.search("whale"), (2..=MAX_SEGMENTS).collect::<Vec<_>>());
+        let (terms, _) = db.list_terms("whale", 0, 10);
+        assert_eq!(terms[0].documents, MAX_SEGMENTS - 1);
+        drop(db);
+
+        let db = open();
+        assert_eq!(db.search("whale"), (2..=MAX_SEGMENTS).collect::<Vec<_>>());
+        assert_eq!(db.search("sea"), vec![1]);
+        assert!(db.is_deleted(0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_blob_store_5() {
+        use ngram::blob::*;
+        let mut store = BlobStore::new();
+        let docs: Vec<String> = (0..2000)
+            .map(|i| format!("Document {} of the corpus, about the white whale. ", i).repeat(5))
+            .collect();
+        for doc in &docs {
+            store.push(Some(doc.clone()));
+        }
+        assert_eq!(store.push(None), docs.len());
+        let total: usize = docs.iter().map(|doc| doc.len()).sum();
+        assert!(total > 4 * BLOCK_SIZE);
+        assert!(store.stored_bytes() * 4 < total);
+
+        for (id, doc) in docs.iter().enumerate().rev() {
+            assert_eq!(store.get(id).as_ref(), Some(doc));
+        }
+        assert_eq!(store.get(docs.len()), None);
+        assert_eq!(store.get(docs.len() + 1), None);
+
+        assert_eq!(store.take(0).as_ref(), Some(&docs[0]));
+        assert_eq!(store.take(0), None);
+        assert!(!store.contains(0));
+        store.set(1, "a sea voyage".to_string());
+        assert_eq!(store.get(1).as_deref(), Some("a sea voyage"));
+        assert_eq!(store.get(2).as_ref(), Some(&docs[2]));
+
+        // Once every document in the first block is gone, its space is given back
+        let before = store.stored_bytes();
+        for id in 2..docs.len() {
+            assert!(store.take(id).is_some());
+        }
+        assert!(store.stored_bytes() < before);
+        assert_eq!(store.get(1).as_deref(), Some("a sea voyage"));
+    }
+
+    #[test]
+    fn test_sharded_blob_store_5() {
+        use ngram::blob::*;
+        let store = ShardedBlobStore::new();
+        let first = store.reserve();
+        let second = store.reserve();
+        let third = store.push(Some("third".to_string()));
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(first), None);
+        assert_eq!(store.get(third).as_deref(), Some("third"));
+
+        // An id several shards along is stored before the ones before it
+        let ids: Vec<usize> = (0..3 * SHARDS).map(|_| store.reserve()).collect();
+        store.insert(ids[2 * SHARDS], Some("late".to_string()));
+        store.insert(ids[SHARDS], Some("early".to_string()));
+        store.insert(second, Some("second".to_string()));
+        store.insert(first, None);
+        assert_eq!(store.get(ids[2 * SHARDS]).as_deref(), Some("late"));
+        assert_eq!(store.get(ids[SHARDS]).as_deref(), Some("early"));
+        assert_eq!(store.get(second).as_deref(), Some("second"));
+        assert!(!store.contains(first));
+        assert!(!store.contains(ids[0]));
+
+        store.set(second, "second again".to_string());
+        assert_eq!(store.take(second).as_deref(), Some("second again"));
+        assert_eq!(store.get(second), None);
+        assert_eq!(store.get(store.len()), None);
+
+        // Full blocks are compressed as more documents are added to each shard
+        let docs: Vec<String> = (0..10000)
+            .map(|i| format!("Document {} of the corpus, about the white whale. ", i).repeat(5))
+            .collect();
+        let before = store.stored_bytes();
+        let ids: Vec<usize> = docs
+            .iter()
+            .map(|doc| store.push(Some(doc.clone())))
+            .collect();
+        let total: usize = docs.iter().map(|doc| doc.len()).sum();
+        assert!(total > 2 * SHARDS * BLOCK_SIZE);
+        assert!((store.stored_bytes() - before) * 2 < total);
+        for (id, doc) in ids.iter().zip(&docs) {
+            assert_eq!(store.get(*id).as_ref(), Some(doc));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_without_storage_5() {
+        use ngram::database::Database;
+        assert!(Database::new().snapshot().is_err());
+    }
+}
+
+// ============================ VOCABULARY ============================
+mod test_vocabulary {
+    use ngram::vocabulary::*;
+
+    fn vocabulary() -> Vocabulary {
+        let vocabulary = Vocabulary::new();
+        let words = [
+            "whale", "whales", "whaling", "wheel", "color", "colour", "colored",
+        ];
+        vocabulary.extend(words.iter().map(|w| w.to_string()));
+        vocabulary
+    }
+
+    #[test]
+    fn test_expand_prefix_5() {
+        let vocabulary = vocabulary();
+        assert_eq!(vocabulary.len(), 7);
+        assert_eq!(
+            vocabulary.expand(PatternKind::Prefix, "whal").unwrap(),
+            vec!["whale", "whales", "whaling"]
+        );
+        assert_eq!(
+            vocabulary.expand(PatternKind::Prefix, "x").unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_page_5() {
+        let vocabulary = vocabulary();
+        assert_eq!(
+            vocabulary.page("wh", 1, 2),
+            (vec!["whales".to_string(), "whaling".to_string()], 4)
+        );
+        assert_eq!(vocabulary.page("wh", 4, 2), (Vec::new(), 4));
+        assert_eq!(vocabulary.page("", 0, 1), (vec!["color".to_string()], 7));
+    }
+
+    #[test]
+    fn test_expand_glob_5() {
+        let vocabulary = vocabulary();
+        assert_eq!(
+            vocabulary.expand(PatternKind::Glob, "whal*").unwrap(),
+            vec!["whale", "whales", "whaling"]
+        );
+        assert_eq!(
+            vocabulary.expand(PatternKind::Glob, "colo?r").unwrap(),
+            vec!["colour"]
+        );
+        assert_eq!(
+            vocabulary.expand(PatternKind::Glob, "*e?").unwrap(),
+            vec!["colored", "whales", "wheel"]
+        );
+    }
+
+    #[test]
+    fn test_expand_regex_5() {
+        let vocabulary = vocabulary();
+        assert_eq!(
+            vocabulary.expand(PatternKind::Regex, "colou?r").unwrap(),
+            vec!["color", "colour"]
+        );
+        assert!(vocabulary.expand(PatternKind::Regex, "colou(r").is_err());
+    }
+}
+
+// ============================ DATABASE ============================
+mod test_database {
+    use ngram::database::*;
+
+    #[test]
+    fn test_ngram_indexed_5() {
+        let db = Database::new().with_max_ngram(3);
+        let id1 = db.publish("to be or not to be".to_string()).unwrap();
+        let id2 = db.publish("be or to".to_string()).unwrap();
+        assert_eq!(db.search("to be"), vec![id1]);
+        assert_eq!(db.search_ngram("to  be   or"), vec![id1]);
+        assert_eq!(db.search_ngram("be or"), vec![id1, id2]);
+        assert_eq!(db.search_ngram("or be"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_ngram_longer_than_indexed_5() {
+        let db = Database::new().with_max_ngram(2);
+        let id1 = db.publish("to be or not to be".to_string()).unwrap();
+        let _id2 = db.publish("to be or to be not".to_string()).unwrap();
+        assert_eq!(db.search("to be or"), Vec::<usize>::new());
+        assert_eq!(db.search_ngram("to be or not"), vec![id1]);
+        assert_eq!(db.search_ngram("or not to be or"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_counts_5() {
+        let db = Database::new().with_max_ngram(2);
+        let id1 = db.publish("to be or not to be".to_string()).unwrap();
+        let id2 = db.publish("not to be".to_string()).unwrap();
+        let mut counts = db.search_counts("be");
+        counts.sort();
+        assert_eq!(counts, vec![(id1, 2), (id2, 1)]);
+        assert_eq!(db.count_ngram("to be"), vec![(id1, 2), (id2, 1)]);
+        assert_eq!(db.count_ngram("not to be"), vec![(id1, 1), (id2, 1)]);
+        assert_eq!(db.search("be"), vec![id1, id2]);
+    }
+
+    #[test]
+    fn test_header_metadata_5() {
+        let db = Database::new();
+        let emma = std::fs::read_to_string("data/austen-emma.txt").unwrap();
+        let kjv = std::fs::read_to_string("data/bible-kjv.txt").unwrap();
+        let parents = std::fs::read_to_string("data/edgeworth-parents.txt").unwrap();
+        let id1 = db.publish(emma.clone()).unwrap();
+        let id2 = db.publish(kjv).unwrap();
+        let id3 = db.publish(parents).unwrap();
+        let id4 = db.publish("no header here".to_string()).unwrap();
+        assert_eq!(
+            db.metadata(id1),
+            Some(Metadata {
+                title: "Emma".to_string(),
+                author: "Jane Austen".to_string(),
+                year: Some(1816),
+            })
+        );
+        assert_eq!(
+            db.metadata(id2),
+            Some(Metadata {
+                title: "The King James Bible".to_string(),
+                author: String::new(),
+                year: None,
+            })
+        );
+        assert_eq!(
+            db.metadata(id3),
+            Some(Metadata {
+                title: "The Parent's Assistant".to_string(),
+                author: "Maria Edgeworth".to_string(),
+                year: None,
+            })
+        );
+        assert_eq!(db.metadata(id4), None);
+        // The header itself is not indexed, but the document is stored as published
+        assert_eq!(db.search("1816]"), Vec::<usize>::new());
+        assert_eq!(db.retrieve(id1), Some(emma));
+    }
+
+    #[test]
+    fn test_timeline_5() {
+        let db = Database::new();
+        db.publish("[A by X 1800]\nwhale whale ship sea".to_string())
+            .unwrap();
+        db.publish("[B by Y 1800]\nship sea sea sea".to_string())
+            .unwrap();
+        db.publish("[C by Z 1850]\nthe whale".to_string()).unwrap();
+        db.publish("[No Year]\nwhale".to_string()).unwrap();
+        let explicit = Metadata {
+            title: "D".to_string(),
+            author: "W".to_string(),
+            year: Some(1900),
+        };
+        db.publish_with_metadata("[C by Z 1850]\nship".to_string(), explicit.clone())
+            .unwrap();
+        assert_eq!(
+            db.timeline("whale", TimelineOptions::default()),
+            vec![(1800, 0.25), (1850, 0.5), (1900, 0.0)]
+        );
+        assert_eq!(db.metadata(4), Some(explicit));
+    }
+
+    #[test]
+    fn test_timeline_options_5() {
+        let db = Database::new();
+        db.publish("[A by X 1800]\nwhale whale ship sea".to_string())
+            .unwrap();
+        db.publish("[B by Y 1802]\nwhale sea".to_string()).unwrap();
+        db.publish("[C by Z 1810]\nthe whale whale ship".to_string())
+            .unwrap();
+        db.publish("[D by W 1810]\nsea sea sea sea".to_string())
+            .unwrap();
+        assert_eq!(db.year_totals(), vec![(1800, 4), (1802, 2), (1810, 8)]);
+
+        let raw = TimelineOptions {
+            normalize: false,
+            smoothing: 0,
+        };
+        assert_eq!(
+            db.timeline("whale", raw),
+            vec![(1800, 2.0), (1802, 1.0), (1810, 2.0)]
+        );
+
+        let smoothed = TimelineOptions {
+            normalize: true,
+            smoothing: 2,
+        };
+        assert_eq!(
+            db.timeline("whale", smoothed),
+            vec![(1800, 0.5), (1802, 0.5), (1810, 0.25)]
+        );
+    }
+
+    #[test]
+    fn test_search_with_tokenizer_5() {
+        use ngram::tokenizer::Pipeline;
+        let db = Database::new()
+            .with_max_ngram(2)
+            .with_tokenizer(Pipeline::default());
+        let id = db
+            .publish("\"Emma,\" he said. EMMA! emma?".to_string())
+            .unwrap();
+        assert_eq!(db.search("Emma"), vec![id]);
+        assert_eq!(db.search_counts("emma,"), vec![(id, 3)]);
+        assert_eq!(db.count_ngram("Emma HE"), vec![(id, 1)]);
+        assert_eq!(db.count_ngram("said emma emma"), vec![(id, 1)]);
+    }
+
+    #[test]
+    fn test_stop_words_5() {
+        use ngram::tokenizer::default_stop_words;
+        let db = Database::new()
+            .with_max_ngram(2)
+            .with_stop_words(default_stop_words());
+        let id1 = db.publish("The whale and the sea".to_string()).unwrap();
+        let id2 = db.publish("whale the sea".to_string()).unwrap();
+        assert_eq!(db.search("the"), Vec::<usize>::new());
+        assert_eq!(db.search("The"), Vec::<usize>::new());
+        assert_eq!(db.search("whale"), vec![id1, id2]);
+        assert_eq!(db.search("the sea"), Vec::<usize>::new());
+        // Sequences with stop words are still found by checking the text
+        assert_eq!(db.count_ngram("the sea"), vec![(id1, 1), (id2, 1)]);
+        assert_eq!(db.count_ngram("whale and the"), vec![(id1, 1)]);
+        assert_eq!(db.count_ngram("and the"), vec![(id1, 1)]);
+    }
+
+    #[test]
+    fn test_boolean_query_5() {
+        use ngram::query::Query;
+        let db = Database::new();
+        let id0 = db.publish("whale ship ahab".to_string()).unwrap();
+        let id1 = db.publish("whale boat".to_string()).unwrap();
+        let id2 = db.publish("whale sea".to_string()).unwrap();
+        let id3 = db.publish("ship boat".to_string()).unwrap();
+        let query = |q: &str| db.query(&Query::parse(q).unwrap());
+        assert_eq!(query("whale AND (ship OR boat) NOT ahab"), vec![id1]);
+        assert_eq!(query("whale (ship OR boat)"), vec![id0, id1]);
+        assert_eq!(query("NOT whale"), vec![id3]);
+        assert_eq!(query("sea OR ahab OR boat"), vec![id0, id1, id2, id3]);
+        assert_eq!(query("kraken AND whale"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_phrase_positions_5() {
+        use ngram::tokenizer::default_stop_words;
+        let db = Database::new().with_stop_words(default_stop_words());
+        let id0 = db
+            .publish("to be or not to be that is the question".to_string())
+            .unwrap();
+        let id1 = db
+            .publish("the question is not to be or to be".to_string())
+            .unwrap();
+        assert_eq!(db.phrase("not to be"), vec![(id0, vec![3]), (id1, vec![3])]);
+        assert_eq!(
+            db.phrase("the question"),
+            vec![(id0, vec![8]), (id1, vec![0])]
+        );
+        assert_eq!(db.phrase("is the question"), vec![(id0, vec![7])]);
+        // Made up only of stop words, so found by reading the text
+        assert_eq!(db.phrase("to be or"), vec![(id0, vec![0]), (id1, vec![4])]);
+        assert_eq!(db.count_ngram("to be"), vec![(id0, 2), (id1, 2)]);
+        assert_eq!(db.phrase("question to"), Vec::new());
+    }
+
+    #[test]
+    fn test_near_5() {
+        use ngram::query::Query;
+        let db = Database::new();
+        let id0 = db
+            .publish("the white whale swam past the ship".to_string())
+            .unwrap();
+        let id1 = db
+            .publish("a ship and then much later a whale".to_string())
+            .unwrap();
+        assert_eq!(db.near("whale", "ship", 2), Vec::new());
+        assert_eq!(db.near("whale", "ship", 3), vec![(id0, vec![2])]);
+        assert_eq!(db.near("ship", "white whale", 3), vec![(id0, vec![1])]);
+        assert_eq!(
+            db.near("whale", "ship", 5),
+            vec![(id0, vec![2]), (id1, vec![1])]
+        );
+        assert_eq!(
+            db.query(&Query::parse("whale NEAR/3 ship").unwrap()),
+            vec![id0]
+        );
+        assert_eq!(
+            db.query(&Query::parse("\"much later\" OR \"white whale\"").unwrap()),
+            vec![id0, id1]
+        );
+    }
+
+    #[test]
+    fn test_search_pattern_5() {
+        use ngram::vocabulary::PatternKind;
+        let db = Database::new();
+        let id0 = db.publish("the whale".to_string()).unwrap();
+        let id1 = db.publish("whaling ship".to_string()).unwrap();
+        let _id2 = db.publish("wheel".to_string()).unwrap();
+        assert_eq!(
+            db.search_pattern(PatternKind::Glob, "whal*").unwrap(),
+            (
+                vec!["whale".to_string(), "whaling".to_string()],
+                vec![id0, id1]
+            )
+        );
+        assert_eq!(
+            db.search_pattern(PatternKind::Regex, "wh(a|ee)l").unwrap(),
+            (vec!["wheel".to_string()], vec![2])
+        );
+    }
+
+    #[test]
+    fn test_pattern_skips_ngrams_5() {
+        use ngram::vocabulary::PatternKind;
+        let db = Database::new().with_max_ngram(2);
+        db.publish("the whale swam by the whaler".to_string())
+            .unwrap();
+        assert_eq!(
+            db.expand(PatternKind::Prefix, "whal").unwrap(),
+            vec!["whale", "whaler"]
+        );
+        assert_eq!(
+            db.expand(PatternKind::Glob, "*e").unwrap(),
+            vec!["the", "whale"]
+        );
+        assert_eq!(db.search_ngram("whale swam"), vec![0]);
+
+        assert!(db.replace(0, "a whale ship".to_string()).unwrap());
+        assert_eq!(
+            db.expand(PatternKind::Glob, "*").unwrap(),
+            vec!["a", "ship", "whale"]
+        );
+        assert!(db.delete(0).unwrap());
+        assert_eq!(
+            db.expand(PatternKind::Glob, "*").unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_search_5() {
+        let db = Database::new();
+        let id0 = db.publish("and he did shew them".to_string()).unwrap();
+        let id1 = db.publish("show me the whale".to_string()).unwrap();
+        let id2 = db.publish("a shoe and a shew".to_string()).unwrap();
+        assert_eq!(
+            db.fuzzy_search("shew", 1),
+            vec![
+                ("shew".to_string(), vec![id0, id2]),
+                ("show".to_string(), vec![id1]),
+            ]
+        );
+        assert!(db
+            .fuzzy_search("shew", 2)
+            .contains(&("shoe".to_string(), vec![id2])));
+        assert_eq!(db.fuzzy_search("whales", 0), Vec::new());
+    }
+
+    #[test]
+    fn test_fuzzy_search_skips_ngrams_5() {
+        let db = Database::new().with_max_ngram(2);
+        db.publish("the whale swam by the whaler".to_string())
+            .unwrap();
+        assert_eq!(
+            db.fuzzy_search("whale", 1),
+            vec![
+                ("whale".to_string(), vec![0]),
+                ("whaler".to_string(), vec![0])
+            ]
+        );
+        assert_eq!(db.fuzzy_search("by", 1), vec![("by".to_string(), vec![0])]);
+    }
+
+    #[test]
+    fn test_ranked_search_5() {
+        let db = Database::new();
+        let id0 = db
+            .publish("the whale the whale the whale".to_string())
+            .unwrap();
+        let id1 = db
+            .publish("a whale among many other words in a much longer text".to_string())
+            .unwrap();
+        let id2 = db.publish("the ship".to_string()).unwrap();
+        let ranked = db.ranked_search("whale", Scoring::default(), 10);
+        let ids: Vec<usize> = ranked.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![id0, id1]);
+        assert!(ranked[0].1 > ranked[1].1);
+
+        // a rarer word counts for more
+        let ranked = db.ranked_search("whale ship", Scoring::default(), 1);
+        assert_eq!(ranked.len(), 1);
+        assert_eq!(ranked[0].0, id2);
+
+        // without length normalization, only the number of occurrences matters
+        let flat = Scoring::Bm25 { k1: 1.2, b: 0.0 };
+        let ranked = db.ranked_search("whale", flat, 10);
+        assert!(ranked[0].1 > ranked[1].1);
+        let ranked = db.ranked_search("whale", Scoring::TfIdf, 10);
+        assert!((ranked[0].1 - 3.0 * ranked[1].1).abs() < 1e-9);
+        assert_eq!(db.ranked_search("kraken", Scoring::TfIdf, 10), Vec::new());
+    }
+
+    #[test]
+    fn test_concordance_5() {
+        let db = Database::new().with_tokenizer(ngram::tokenizer::Pipeline::default());
+        db.publish("Call me Ishmael.".to_string()).unwrap();
+        let id = db
+            .publish(
+                "[Hamlet by William Shakespeare 1603]\nTo be, or not to be:\nthat is the question."
+                    .to_string(),
+            )
+            .unwrap();
+        let lines = db.concordance("to be", 2, 10);
+        assert_eq!(
+            lines,
+            vec![
+                ConcordanceLine {
+                    id,
+                    offset: 0,
+                    left: "".to_string(),
+                    keyword: "To be".to_string(),
+                    right: ", or not".to_string(),
+                },
+                ConcordanceLine {
+                    id,
+                    offset: 4,
+                    left: "or not".to_string(),
+                    keyword: "to be".to_string(),
+                    right: ": that is".to_string(),
+                },
+            ]
+        );
+        assert_eq!(db.concordance("be", 0, 1).len(), 1);
+        assert_eq!(db.concordance("question", 1, 10)[0].right, "".to_string());
+    }
+
+    #[test]
+    fn test_snippets_5() {
+        let db = Database::new().with_tokenizer(ngram::tokenizer::Pipeline::default());
+        let id = db
+            .publish(
+                "Call me Ishmael. Some years ago, never mind how long precisely,\nhaving little \
+             or no money in my purse, I thought I would sail about a little."
+                    .to_string(),
+            )
+            .unwrap();
+        db.publish("Nothing to see here.".to_string()).unwrap();
+        let snippets = db.snippets("little money", Scoring::default(), 10, 6);
+        assert_eq!(snippets.len(), 1);
+        let snippet = &snippets[0];
+        assert_eq!(snippet.id, id);
+        assert_eq!(snippet.text, "precisely, having little or no money");
+        let highlighted: Vec<&str> = snippet
+            .highlights
+            .iter()
+            .map(|range| &snippet.text[range.clone()])
+            .collect();
+        assert_eq!(highlighted, vec!["little", "money"]);
+        assert_eq!(
+            db.snippets("ishmael", Scoring::default(), 10, 100)[0]
+                .highlights
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_collocations_5() {
+        let db = Database::new().with_stop_words(ngram::tokenizer::parse_stop_words("the a of"));
+        db.publish("the deep blue sea and the open sea".to_string())
+            .unwrap();
+        db.publish("a deep sea of blue water".to_string()).unwrap();
+        db.publish("the ship sailed to the harbour and the town".to_string())
+            .unwrap();
+        db.publish("blue is a colour and the town is grey".to_string())
+            .unwrap();
+
+        let by_count = db.collocations("sea", 2, Association::LogLikelihood, 2, 10);
+        let words: Vec<&str> = by_count.iter().map(|c| c.word.as_str()).collect();
+        assert_eq!(words, vec!["deep", "blue"]);
+        assert_eq!(by_count[0].count, 2);
+        assert_eq!(by_count[1].count, 2);
+        assert!(by_count.iter().all(|c| c.pmi > 0.0));
+
+        let all = db.collocations("sea", 2, Association::Pmi, 1, 10);
+        assert!(all.windows(2).all(|w| w[0].pmi >= w[1].pmi));
+        assert!(all.iter().all(|c| c.word != "the" && c.word != "sea"));
+        assert_eq!(db.collocations("sea", 2, Association::Pmi, 1, 1).len(), 1);
+        assert_eq!(
+            db.collocations("kraken", 2, Association::Pmi, 1, 10),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_stats_5() {
+        let db = Database::new()
+            .with_max_ngram(2)
+            .with_stop_words(ngram::tokenizer::parse_stop_words("the a"));
+        db.publish("the whale and the sea and the whale".to_string())
+            .unwrap();
+        let id = db
+            .publish("[Moby Dick by Herman Melville 1851]\na whale".to_string())
+            .unwrap();
+
+        let stats = db.stats(2);
+        assert_eq!(stats.documents, 2);
+        assert_eq!(stats.tokens, 10);
+        assert_eq!(stats.vocabulary, 3);
+        assert_eq!(stats.average_length, 5.0);
+        assert_eq!(
+            stats.top_terms,
+            vec![("whale".to_string(), 3), ("and".to_string(), 2)]
+        );
+
+        let doc = db.doc_stats(id, 10).unwrap();
+        assert_eq!(doc.tokens, 2);
+        assert_eq!(doc.distinct, 1);
+        assert_eq!(doc.top_terms, vec![("whale".to_string(), 1)]);
+        assert_eq!(db.doc_stats(id + 1, 10), None);
+        assert_eq!(Database::new().stats(5).average_length, 0.0);
+    }
+
+    #[test]
+    fn test_list_terms_5() {
+        let db = Database::new().with_max_ngram(2);
+        db.publish("whale whaling whale ship".to_string()).unwrap();
+        db.publish("whale boat".to_string()).unwrap();
+        let frequency = |term: &str, documents, occurrences| TermFrequency {
+            term: term.to_string(),
+            documents,
+            occurrences,
+        };
+
+        let (terms, total) = db.list_terms("", 0, 10);
+        assert_eq!(total, 4);
+        assert_eq!(
+            terms,
+            vec![
+                frequency("boat", 1, 1),
+                frequency("ship", 1, 1),
+                frequency("whale", 2, 3),
+                frequency("whaling", 1, 1),
+            ]
+        );
+        assert_eq!(
+            db.list_terms("wh", 1, 10),
+            (vec![frequency("whaling", 1, 1)], 2)
+        );
+        assert_eq!(db.list_terms("", 1, 2).0, terms[1..3].to_vec());
+        assert_eq!(db.list_terms("x", 0, 10), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn test_delete_5() {
+        let db = Database::new().with_max_ngram(2);
+        let id0 = db
+            .publish("[Moby Dick by Herman Melville 1851]\nthe white whale".to_string())
+            .unwrap();
+        let id1 = db
+            .publish("[Typee by Herman Melville 1846]\nthe whaling ship".to_string())
+            .unwrap();
+        assert!(db.delete(id0).unwrap());
+        assert!(!db.delete(id0).unwrap());
+        assert!(!db.delete(id1 + 1).unwrap());
+
+        assert!(db.is_deleted(id0));
+        assert!(!db.is_deleted(id1));
+        assert_eq!(db.retrieve(id0), None);
+        assert_eq!(db.search("the"), vec![id1]);
+        assert_eq!(db.search("whale"), Vec::<usize>::new());
+        assert_eq!(db.search_ngram("white whale"), Vec::<usize>::new());
+        assert_eq!(
+            db.query(&ngram::query::Query::parse("NOT ship").unwrap()),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            db.expand(ngram::vocabulary::PatternKind::Prefix, "wh")
+                .unwrap(),
+            vec!["whaling"]
+        );
+        assert_eq!(db.fuzzy_search("whale", 1), Vec::new());
+        assert_eq!(db.year_totals(), vec![(1846, 3), (1851, 0)]);
+        assert_eq!(db.stats(0).documents, 1);
+
+        // the id of a deleted document is never reused
+        let id2 = db.publish("the white whale".to_string()).unwrap();
+        assert_eq!(id2, 2);
+        assert_eq!(db.search("whale"), vec![id2]);
+        assert_eq!(db.fuzzy_search("whale", 0).len(), 1);
+    }
+
+    #[test]
+    fn test_replace_5() {
+        let db = Database::new().with_max_ngram(2);
+        let id0 = db
+            .publish("[Moby Dick by Herman Melville 1851]\nthe white whael".to_string())
+            .unwrap();
+        let id1 = db.publish("the whaling ship".to_string()).unwrap();
+        let corrected = "[Moby Dick by Herman Melville 1851]\nthe great white whale".to_string();
+        assert!(db.replace(id0, corrected.clone()).unwrap());
+
+        assert_eq!(db.retrieve(id0), Some(corrected));
+        assert_eq!(db.search("whael"), Vec::<usize>::new());
+        assert_eq!(db.search("whale"), vec![id0]);
+        assert_eq!(db.search("the"), vec![id0, id1]);
+        assert_eq!(db.phrase("white whale"), vec![(id0, vec![2])]);
+        assert_eq!(db.fuzzy_search("whael", 0), Vec::new());
+        assert_eq!(db.year_totals(), vec![(1851, 4)]);
+        assert_eq!(db.metadata(id0).unwrap().title, "Moby Dick");
+
+        // without a header the metadata is kept
+        assert!(db.replace(id0, "a white whale".to_string()).unwrap());
+        assert_eq!(db.year_totals(), vec![(1851, 3)]);
+        assert_eq!(db.search("the"), vec![id1]);
+
+        assert!(db.delete(id1).unwrap());
+        assert!(!db.replace(id1, "the ship".to_string()).unwrap());
+        assert!(!db.replace(id1 + 1, "the ship".to_string()).unwrap());
+        assert_eq!(db.search("ship"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_replace_between_postings_5() {
+        let db = Database::new();
+        let ids: Vec<usize> = ["whale one", "two whale", "three whale"]
+            .iter()
+            .map(|doc| db.publish(doc.to_string()).unwrap())
+            .collect();
+        // the postings of `whale` are rewritten around the replaced and deleted documents
+        assert!(db.replace(ids[1], "a white whale".to_string()).unwrap());
+        assert_eq!(
+            db.phrase("whale"),
+            vec![(ids[0], vec![0]), (ids[1], vec![2]), (ids[2], vec![1])]
+        );
+        assert!(db.delete(ids[0]).unwrap());
+        assert_eq!(
+            db.phrase("whale"),
+            vec![(ids[1], vec![2]), (ids[2], vec![1])]
+        );
+        assert!(db.delete(ids[2]).unwrap());
+        assert!(db.delete(ids[1]).unwrap());
+        assert_eq!(db.search("whale"), Vec::<usize>::new());
+        assert_eq!(db.list_terms("", 0, 10).1, 0);
+    }
+
+    #[test]
+    fn test_publish_checked_5() {
+        let text = "it was the best of times it was the worst of times it was the age of \
+                    wisdom it was the age of foolishness it was the epoch of belief";
+        let db = Database::new()
+            .with_duplicate_policy(DuplicatePolicy::Reject)
+            .with_near_duplicate_threshold(0.5);
+        let id = db.publish_checked(text.to_string(), None).unwrap().id;
+        assert!(matches!(
+            db.publish_checked(text.to_string(), None),
+            Err(PublishError::Duplicate(DuplicateError { existing })) if existing == id
+        ));
+        let edited = db
+            .publish_checked(text.replace("foolishness", "folly"), None)
+            .unwrap();
+        assert_eq!(edited.near_duplicates.len(), 1);
+        assert_eq!(edited.near_duplicates[0].0, id);
+        let other = db
+            .publish_checked("call me ishmael".to_string(), None)
+            .unwrap();
+        assert_eq!(other.near_duplicates, Vec::new());
+
+        // a deleted document is no longer a duplicate
+        assert!(db.delete(id).unwrap());
+        let again = db.publish_checked(text.to_string(), None).unwrap();
+        assert_eq!(
+            again.near_duplicates,
+            vec![(edited.id, edited.near_duplicates[0].1)]
+        );
+
+        let db = Database::new().with_duplicate_policy(DuplicatePolicy::ReturnExisting);
+        let id = db.publish_checked(text.to_string(), None).unwrap().id;
+        let existing = db.publish_checked(text.to_string(), None).unwrap();
+        assert_eq!(existing.id, id);
+        assert_eq!(db.stats(0).documents, 1);
+
+        let db = Database::new();
+        let first = db.publish_checked(text.to_string(), None).unwrap();
+        let second = db.publish_checked(text.to_string(), None).unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(second.near_duplicates, vec![(first.id, 1.0)]);
+    }
+
+    #[test]
+    fn test_ranked_search_while_publishing_5() {
+        use std::sync::Arc;
+        let db = Arc::new(Database::new());
+        let publisher = {
+            let db = Arc::clone(&db);
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    db.publish(format!("the white whale number {}", i)).unwrap();
+                }
+            })
+        };
+        let searchers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = Arc::clone(&db);
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        db.ranked_search("white whale", Scoring::default(), 5);
+                        db.snippets("whale", Scoring::default(), 5, 3);
+                    }
+                })
+            })
+            .collect();
+        publisher.join().unwrap();
+        for searcher in searchers {
+            searcher.join().unwrap();
+        }
+        assert_eq!(
+            db.ranked_search("whale", Scoring::default(), 500).len(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_retrieve_while_publishing_5() {
+        use std::collections::HashSet;
+        use std::sync::Arc;
+        let db = Arc::new(Database::new());
+        db.publish("call me ishmael".to_string()).unwrap();
+        let publishers: Vec<_> = (0..4)
+            .map(|t| {
+                let db = Arc::clone(&db);
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        let id = db.publish(format!("thread {} document {}", t, i)).unwrap();
+                        assert_eq!(
+                            db.retrieve(id),
+                            Some(format!("thread {} document {}", t, i))
+                        );
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..200 {
+            assert_eq!(db.retrieve(0).as_deref(), Some("call me ishmael"));
+            for id in 1..=200 {
+                if let Some(doc) = db.retrieve(id) {
+                    assert!(doc.starts_with("thread "));
+                }
+            }
+        }
+        for publisher in publishers {
+            publisher.join().unwrap();
+        }
+        let docs: HashSet<String> = (1..=200).filter_map(|id| db.retrieve(id)).collect();
+        assert_eq!(docs.len(), 200);
+        assert_eq!(db.search("document").len(), 200);
+    }
+
+    #[test]
+    fn test_ngram_empty_5() {
+        let db = Database::new();
+        db.publish("call me ishmael".to_string()).unwrap();
+        assert_eq!(db.search_ngram("   "), Vec::<usize>::new());
+    }
 }
 
 // ============================ ARGUMENTS ============================
@@ -200,7 +2107,7 @@ mod test_serialize {
 mod integration {
     use super::*;
     use ngram::message::*;
-    use ngram::{client, server};
+    use ngram::{client, database, server};
     use std::fs;
     use std::sync::{Arc, Mutex};
     use std::thread::{self, JoinHandle};
@@ -230,7 +2137,7 @@ mod integration {
 
         let client = client::Client::new("127.0.0.1", port);
         let response = client.publish_from_path("data/austen-emma.txt");
-        assert!(matches!(response, Some(Response::PublishSuccess(_))));
+        assert!(matches!(response, Some(Response::PublishSuccess(_, _))));
         server.stop();
     }
 
@@ -254,7 +2161,7 @@ mod integration {
 
         let response = client.publish_from_path("data/austen-emma.txt");
         let id = match response {
-            Some(Response::PublishSuccess(id)) => id,
+            Some(Response::PublishSuccess(id, _)) => id,
             _ => panic!("Failed to publish data/austen-emma.txt"),
         };
         let response = client.search("the");
@@ -269,11 +2176,11 @@ mod integration {
 
         let client = client::Client::new("127.0.0.1", port);
         let id1 = match client.publish_from_path("data/austen-emma.txt") {
-            Some(Response::PublishSuccess(id)) => id,
+            Some(Response::PublishSuccess(id, _)) => id,
             _ => panic!("Failed to publish data/austen-emma.txt"),
         };
         let id2 = match client.publish_from_path("data/austen-persuasion.txt") {
-            Some(Response::PublishSuccess(id)) => id,
+            Some(Response::PublishSuccess(id, _)) => id,
             _ => panic!("Failed to publish data/austen-persuasion.txt"),
         };
 
@@ -295,11 +2202,11 @@ mod integration {
 
         let client = client::Client::new("127.0.0.1", port);
         let _id1 = match client.publish_from_path("data/austen-persuasion.txt") {
-            Some(Response::PublishSuccess(id)) => id,
+            Some(Response::PublishSuccess(id, _)) => id,
             _ => panic!("Failed to publish data/austen-persuasion.txt"),
         };
         let id2 = match client.publish_from_path("data/austen-emma.txt") {
-            Some(Response::PublishSuccess(id)) => id,
+            Some(Response::PublishSuccess(id, _)) => id,
             _ => panic!("Failed to publish data/austen-emma.txt"),
         };
 
@@ -315,7 +2222,7 @@ mod integration {
 
         let client = client::Client::new("127.0.0.1", port);
         let id = match client.publish_from_path("data/austen-emma.txt") {
-            Some(Response::PublishSuccess(id)) => id,
+            Some(Response::PublishSuccess(id, _)) => id,
             _ => panic!("Failed to publish data/austen-emma.txt"),
         };
         let doc = std::fs::read_to_string("data/austen-emma.txt").unwrap();
@@ -324,6 +2231,69 @@ mod integration {
         server.stop();
     }
 
+    #[test]
+    fn test_delete_5() {
+        let port = 7891;
+        let (server, _handle) = start_server(port);
+
+        let client = client::Client::new("127.0.0.1", port);
+        let id = match client.publish_from_path("data/austen-emma.txt") {
+            Some(Response::PublishSuccess(id, _)) => id,
+            _ => panic!("Failed to publish data/austen-emma.txt"),
+        };
+        assert_eq!(client.delete(id), Some(Response::DeleteSuccess(id)));
+        assert_eq!(client.delete(id), Some(Response::Failure));
+        assert_eq!(client.retrieve(id), Some(Response::Deleted(id)));
+        assert_eq!(client.retrieve(id + 1), Some(Response::Failure));
+        assert_eq!(client.search("the"), Some(Response::SearchSuccess(vec![])));
+        server.stop();
+    }
+
+    #[test]
+    fn test_publish_duplicate_5() {
+        let port = 7892;
+        let server = Arc::new(server::Server::with_database(
+            database::Database::new().with_duplicate_policy(database::DuplicatePolicy::Reject),
+        ));
+        let _handle = thread::spawn({
+            let server = Arc::clone(&server);
+            move || server.run(port)
+        });
+        thread::sleep(Duration::from_millis(500));
+
+        let client = client::Client::new("127.0.0.1", port);
+        let id = match client.publish_from_path("data/austen-emma.txt") {
+            Some(Response::PublishSuccess(id, similar)) if similar.is_empty() => id,
+            _ => panic!("Failed to publish data/austen-emma.txt"),
+        };
+        let response = client.publish_from_path("data/austen-emma.txt");
+        assert_eq!(response, Some(Response::Duplicate(id)));
+        server.stop();
+    }
+
+    #[test]
+    fn test_search_ngram_5() {
+        let port = 7890;
+        let server = Arc::new(server::Server::with_database(
+            database::Database::new().with_max_ngram(2),
+        ));
+        let _handle = thread::spawn({
+            let server = Arc::clone(&server);
+            move || server.run(port)
+        });
+        thread::sleep(Duration::from_millis(500));
+
+        let client = client::Client::new("127.0.0.1", port);
+        let id = match client.publish_from_path("data/melville-moby_dick.txt") {
+            Some(Response::PublishSuccess(id, _)) => id,
+            _ => panic!("Failed to publish data/melville-moby_dick.txt"),
+        };
+        let _ = client.publish_from_path("data/austen-emma.txt");
+        let response = client.search_ngram("a queer handkerchief, mockingly");
+        assert_eq!(response, Some(Response::SearchSuccess(vec![id])));
+        server.stop();
+    }
+
     #[test]
     fn test_server_stress_test_10() {
         let port = 7889;
@@ -352,7 +2322,7 @@ mod integration {
 
         let queue = Arc::new(Mutex::new(paths));
         println!("Adding docs...");
-        let now = std::time::Instant::now();
+        let _now = std::time::Instant::now();
         let handles = (0..THREADS)
             .map(|i| {
                 thread::spawn({
@@ -386,7 +2356,7 @@ mod integration {
             let response = client.search(word);
             assert!(matches!(response, Some(Response::SearchSuccess(_))));
         }
-        // println!("Sequential search took {:?}", _now.elapsed);
+        // println!("Sequential search took {:?}", _now.elapsed());
 
         // Search docs in parallel
         let word_queue = Arc::new(Mutex::new(words));